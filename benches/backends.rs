@@ -0,0 +1,38 @@
+//! Times both backends on a recursive fib(30) and a long-running loop. Run with
+//! `cargo bench`.
+
+use std::io;
+use std::time::Instant;
+
+use loxide::interpreter::{Backend, Interpreter};
+use loxide::lox::{self, Status};
+
+const PROGRAMS: [(&str, &str); 2] = [
+    (
+        "fib(30)",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }\n\
+         print fib(30);",
+    ),
+    (
+        "300k-iteration loop",
+        "var sum = 0;\n\
+         for (var i = 0; i < 300000; i = i + 1) sum = sum + i;\n\
+         print sum;",
+    ),
+];
+
+fn main() {
+    for (name, source) in PROGRAMS {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut interpreter = Interpreter::builder()
+                .backend(backend)
+                .stdout(io::sink())
+                .build();
+            let start = Instant::now();
+            let status = lox::run(source.to_string(), name, &mut interpreter);
+            assert_eq!(status, Status::Ok);
+            let backend = format!("{:?}", backend);
+            println!("{:<20} {:<10} {:.2?}", name, backend, start.elapsed());
+        }
+    }
+}
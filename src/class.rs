@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use crate::function::LoxFunction;
+use crate::interpreter::Interpreter;
+use crate::parser::RuntimeError;
+use crate::scanner::Token;
+use crate::value::Value;
+
+/// A class declared in Lox. Calling it creates a new instance.
+pub struct LoxClass {
+    name: String,
+    methods: HashMap<String, Arc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(name: &str, methods: HashMap<String, Arc<LoxFunction>>) -> Self {
+        Self {
+            name: name.to_string(),
+            methods,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The number of arguments `init` takes, or none if there's no `init`.
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    /// Creates an instance and runs `init` on it with `arguments`. The caller has
+    /// already checked the argument count.
+    pub fn call(
+        self: &Arc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Arc::new(LoxInstance::new(self.clone()));
+        if let Some(init) = self.find_method("init") {
+            init.bind(instance.clone()).call(interpreter, arguments)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<&Arc<LoxFunction>> {
+        self.methods.get(name)
+    }
+
+    pub fn method_names(&self) -> Vec<String> {
+        self.methods.keys().cloned().collect()
+    }
+}
+
+impl Debug for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// An object created by calling a class. Fields aren't declared: setting one that
+/// doesn't exist yet adds it.
+pub struct LoxInstance {
+    class: Arc<LoxClass>,
+    fields: Mutex<HashMap<String, Value>>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Arc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: Mutex::default(),
+        }
+    }
+
+    pub fn class(&self) -> &Arc<LoxClass> {
+        &self.class
+    }
+
+    /// Looks up a field, or else a method bound to this instance. Fields shadow
+    /// methods with the same name.
+    pub fn get(self: &Arc<Self>, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.fields.lock().unwrap().get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+        match self.class.find_method(&name.lexeme) {
+            Some(method) => Ok(Value::Function(Arc::new(method.bind(self.clone())))),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &format!("Undefined property '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    pub fn set(&self, name: &Token, value: Value) {
+        self.fields
+            .lock()
+            .unwrap()
+            .insert(name.lexeme.clone(), value);
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        self.fields.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl Debug for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
@@ -0,0 +1,306 @@
+use std::sync::Arc;
+
+use crate::parser::{Expr, ExprId, Function, Stmt};
+use crate::scanner::{Token, TokenType};
+use crate::value::Value;
+
+#[derive(Debug)]
+pub enum OpCode {
+    Constant(usize),
+    // Rejects a non-number left operand of an arithmetic operator before the right
+    // operand is evaluated, matching the tree-walker.
+    CheckNumber(Token),
+    Unary(Token),
+    Binary(Token),
+    GetVariable {
+        name: Token,
+        id: ExprId,
+    },
+    // Assigns the value on top of the stack, leaving it there as the result.
+    SetVariable {
+        name: Token,
+        id: ExprId,
+    },
+    // Short-circuits `and` and `or`. If the value on top of the stack is truthy (for
+    // `or`) or falsey (for `and`), jumps to `target`, leaving it as the result.
+    // Otherwise it's popped and the right operand runs next.
+    ShortCircuit {
+        when_truthy: bool,
+        target: usize,
+    },
+    // Calls the value below the top `count` values with them as arguments.
+    Call {
+        paren: Token,
+        count: usize,
+    },
+    // Replaces the instance on top of the stack with the named property.
+    GetProperty(Token),
+    // Replaces the array below the top value with its element at that index.
+    Index(Token),
+    // Sets the named field of the instance below the top value to that value,
+    // leaving the value as the result.
+    SetProperty(Token),
+    Pop,
+    Print,
+    // Counts a loop iteration against the step limit.
+    Step(Token),
+    // Binds the name to the value on top of the stack in the current scope.
+    DefineVariable(Token),
+    Function(Arc<Function>),
+    Class {
+        name: Token,
+        methods: Vec<Arc<Function>>,
+    },
+    PushScope,
+    PopScope,
+    Jump(usize),
+    // Pops the value on top of the stack and jumps if it's falsey.
+    JumpIfFalse(usize),
+    // Ends the chunk with the value on top of the stack as its result.
+    Return,
+}
+
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Value>,
+}
+
+enum Pending<'a> {
+    Compile(&'a Expr),
+    Emit(OpCode),
+    // Emits a `ShortCircuit` whose target isn't known yet.
+    EmitJump { when_truthy: bool },
+    // Points the most recent unpatched jump at the next instruction.
+    PatchJump,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk {
+            code: Vec::new(),
+            constants: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        self.constants.push(value);
+        self.emit(OpCode::Constant(self.constants.len() - 1));
+    }
+
+    // Points the jump at `index` to the next instruction.
+    fn patch_jump(&mut self, index: usize) {
+        let end = self.code.len();
+        if let OpCode::Jump(target) | OpCode::JumpIfFalse(target) = &mut self.code[index] {
+            *target = end;
+        }
+    }
+}
+
+impl Function {
+    /// The body compiled for the VM. It's compiled the first time it's needed and
+    /// shared by every closure created from this declaration.
+    pub fn chunk(&self) -> &Chunk {
+        self.chunk.get_or_init(|| compile_program(&self.body))
+    }
+}
+
+/// Compiles a single expression, leaving its value as the chunk's result.
+pub fn compile(expr: &Expr) -> Chunk {
+    let mut chunk = Chunk::new();
+    compile_expression(&mut chunk, expr);
+    chunk
+}
+
+/// Compiles statements, such as a whole program or a function body. The chunk's
+/// result is the value of the first `return` run, or nil.
+pub fn compile_program(statements: &[Stmt]) -> Chunk {
+    let mut chunk = Chunk::new();
+    for statement in statements {
+        compile_statement(&mut chunk, statement);
+    }
+    chunk
+}
+
+// Statements recurse, since the parser limits how deeply they can nest.
+fn compile_statement(chunk: &mut Chunk, statement: &Stmt) {
+    match statement {
+        Stmt::Block { statements } => {
+            chunk.emit(OpCode::PushScope);
+            for statement in statements {
+                compile_statement(chunk, statement);
+            }
+            chunk.emit(OpCode::PopScope);
+        }
+        Stmt::Class { name, methods } => {
+            chunk.emit(OpCode::Class {
+                name: name.clone(),
+                methods: methods.clone(),
+            });
+        }
+        Stmt::Expression { expression } => {
+            compile_expression(chunk, expression);
+            chunk.emit(OpCode::Pop);
+        }
+        Stmt::Function(function) => {
+            chunk.emit(OpCode::Function(function.clone()));
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            compile_expression(chunk, condition);
+            let skip_then = chunk.emit(OpCode::JumpIfFalse(0));
+            compile_statement(chunk, then_branch);
+            match else_branch {
+                Some(else_branch) => {
+                    let skip_else = chunk.emit(OpCode::Jump(0));
+                    chunk.patch_jump(skip_then);
+                    compile_statement(chunk, else_branch);
+                    chunk.patch_jump(skip_else);
+                }
+                None => chunk.patch_jump(skip_then),
+            }
+        }
+        Stmt::Print { expression } => {
+            compile_expression(chunk, expression);
+            chunk.emit(OpCode::Print);
+        }
+        Stmt::Return { value, .. } => {
+            match value {
+                Some(value) => compile_expression(chunk, value),
+                None => chunk.emit_constant(Value::Nil),
+            }
+            chunk.emit(OpCode::Return);
+        }
+        Stmt::Var { name, initializer } => {
+            match initializer {
+                Some(initializer) => compile_expression(chunk, initializer),
+                None => chunk.emit_constant(Value::Nil),
+            }
+            chunk.emit(OpCode::DefineVariable(name.clone()));
+        }
+        Stmt::While {
+            keyword,
+            condition,
+            body,
+        } => {
+            let start = chunk.emit(OpCode::Step(keyword.clone()));
+            compile_expression(chunk, condition);
+            let exit = chunk.emit(OpCode::JumpIfFalse(0));
+            compile_statement(chunk, body);
+            chunk.emit(OpCode::Jump(start));
+            chunk.patch_jump(exit);
+        }
+    }
+}
+
+fn compile_expression(chunk: &mut Chunk, expr: &Expr) {
+    // Like `Expr::interpret`, this avoids recursion so deep trees can't overflow the stack.
+    let mut pending = vec![Pending::Compile(expr)];
+    let mut jumps = Vec::new();
+
+    while let Some(item) = pending.pop() {
+        match item {
+            Pending::Emit(op) => chunk.code.push(op),
+            Pending::EmitJump { when_truthy } => {
+                jumps.push(chunk.code.len());
+                chunk.code.push(OpCode::ShortCircuit {
+                    when_truthy,
+                    target: 0,
+                });
+            }
+            Pending::PatchJump => {
+                let end = chunk.code.len();
+                if let OpCode::ShortCircuit { target, .. } = &mut chunk.code[jumps.pop().unwrap()] {
+                    *target = end;
+                }
+            }
+            Pending::Compile(expr) => match expr {
+                Expr::Literal { value } => chunk.emit_constant(value.clone()),
+                Expr::Variable { id, name } | Expr::This { id, keyword: name } => {
+                    chunk.code.push(OpCode::GetVariable {
+                        name: name.clone(),
+                        id: *id,
+                    })
+                }
+                Expr::Assign { id, name, value } => {
+                    pending.push(Pending::Emit(OpCode::SetVariable {
+                        name: name.clone(),
+                        id: *id,
+                    }));
+                    pending.push(Pending::Compile(value));
+                }
+                Expr::Logical {
+                    left,
+                    operator,
+                    right,
+                } => {
+                    let when_truthy = matches!(operator.token_type, TokenType::Or);
+                    pending.push(Pending::PatchJump);
+                    pending.push(Pending::Compile(right));
+                    pending.push(Pending::EmitJump { when_truthy });
+                    pending.push(Pending::Compile(left));
+                }
+                Expr::Call {
+                    callee,
+                    paren,
+                    arguments,
+                } => {
+                    pending.push(Pending::Emit(OpCode::Call {
+                        paren: paren.clone(),
+                        count: arguments.len(),
+                    }));
+                    pending.extend(arguments.iter().rev().map(Pending::Compile));
+                    pending.push(Pending::Compile(callee));
+                }
+                Expr::Get { object, name } => {
+                    pending.push(Pending::Emit(OpCode::GetProperty(name.clone())));
+                    pending.push(Pending::Compile(object));
+                }
+                Expr::Grouping { expression } => pending.push(Pending::Compile(expression)),
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => {
+                    pending.push(Pending::Emit(OpCode::Index(bracket.clone())));
+                    pending.push(Pending::Compile(index));
+                    pending.push(Pending::Compile(object));
+                }
+                Expr::Set {
+                    object,
+                    name,
+                    value,
+                } => {
+                    pending.push(Pending::Emit(OpCode::SetProperty(name.clone())));
+                    pending.push(Pending::Compile(value));
+                    pending.push(Pending::Compile(object));
+                }
+                Expr::Unary { operator, right } => {
+                    pending.push(Pending::Emit(OpCode::Unary(operator.clone())));
+                    pending.push(Pending::Compile(right));
+                }
+                Expr::Binary {
+                    left,
+                    operator,
+                    right,
+                } => {
+                    pending.push(Pending::Emit(OpCode::Binary(operator.clone())));
+                    pending.push(Pending::Compile(right));
+                    if let TokenType::Minus | TokenType::Slash | TokenType::Star =
+                        operator.token_type
+                    {
+                        pending.push(Pending::Emit(OpCode::CheckNumber(operator.clone())));
+                    }
+                    pending.push(Pending::Compile(left));
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,735 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+/// A source of REPL input lines.
+pub trait LineReader {
+    /// Shows `prompt` and reads one line without its newline, or `None` at end of input.
+    /// `completer` supplies candidates when Tab is pressed.
+    fn read_line(&mut self, prompt: &str, completer: &dyn Completer) -> io::Result<Option<String>>;
+}
+
+/// What the word being completed is part of.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompletionContext {
+    // A keyword or a variable name.
+    Name,
+    // A property after `object.`.
+    Property { object: String },
+}
+
+pub trait Completer {
+    /// Every candidate starting with `prefix`, sorted.
+    fn completions(&self, prefix: &str, context: CompletionContext) -> Vec<String>;
+}
+
+/// Reads whole lines with no editing, for piped input.
+pub struct PlainReader<R: BufRead> {
+    input: R,
+}
+
+impl<R: BufRead> PlainReader<R> {
+    pub fn new(input: R) -> Self {
+        Self { input }
+    }
+}
+
+impl<R: BufRead> LineReader for PlainReader<R> {
+    fn read_line(&mut self, prompt: &str, _: &dyn Completer) -> io::Result<Option<String>> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        // EOF if bytes = 0
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
+}
+
+/// The reader for the REPL: line editing on a terminal, plain reads otherwise.
+/// History is only kept, and saved to `history`, when editing.
+#[cfg_attr(not(feature = "line-editing"), allow(unused_variables))]
+pub fn stdin_reader(history: Option<HistoryFile>) -> Box<dyn LineReader> {
+    #[cfg(feature = "line-editing")]
+    if io::IsTerminal::is_terminal(&io::stdin()) {
+        return Box::new(terminal::TerminalReader::new(history));
+    }
+    Box::new(PlainReader::new(io::stdin().lock()))
+}
+
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Where REPL history is kept between sessions, and how many entries to keep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryFile {
+    pub path: PathBuf,
+    pub limit: usize,
+}
+
+impl HistoryFile {
+    /// `$XDG_DATA_HOME/loxide/history`, falling back to `~/.loxide_history`. The
+    /// size comes from `LOXIDE_HISTORY_SIZE` if it's set to a number.
+    pub fn default_location() -> Option<Self> {
+        let path = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("loxide").join("history"),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".loxide_history"),
+        };
+        let limit = env::var("LOXIDE_HISTORY_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_SIZE);
+        Some(Self { path, limit })
+    }
+
+    /// Reads the saved entries. A missing or unreadable file is an empty history.
+    pub fn load(&self) -> Vec<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => trim_history(text.lines().map(str::to_string).collect(), self.limit),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn save(&self, entries: &[String]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let entries = trim_history(entries.to_vec(), self.limit);
+        let mut text = entries.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        fs::write(&self.path, text)
+    }
+}
+
+// Drops consecutive duplicates and all but the last `limit` entries.
+fn trim_history(mut entries: Vec<String>, limit: usize) -> Vec<String> {
+    entries.dedup();
+    let excess = entries.len().saturating_sub(limit);
+    entries.split_off(excess)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Backspace,
+    Enter,
+    Tab,
+    // Ctrl-D
+    Eof,
+    // Ctrl-C
+    Interrupt,
+    // Anything the editor doesn't act on.
+    Ignored,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    // The line is still being edited.
+    Pending,
+    Line(String),
+    // Ctrl-C threw the line away; start a fresh one.
+    Cancelled,
+    Eof,
+}
+
+/// The editing state behind the terminal reader: the line, the cursor, and the
+/// lines entered earlier in the session.
+#[derive(Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    // Which history entry is shown; `history.len()` means the line being typed.
+    position: usize,
+    // The line being typed, kept while browsing history.
+    draft: Vec<char>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_history(history: Vec<String>) -> Self {
+        Self {
+            position: history.len(),
+            history,
+            ..Self::default()
+        }
+    }
+
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn handle(&mut self, key: Key) -> Event {
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+            }
+            Key::Up => {
+                if self.position > 0 {
+                    if self.position == self.history.len() {
+                        self.draft = self.line.clone();
+                    }
+                    self.position -= 1;
+                    self.show(self.history[self.position].chars().collect());
+                }
+            }
+            Key::Down => {
+                if self.position < self.history.len() {
+                    self.position += 1;
+                    let line = match self.history.get(self.position) {
+                        Some(entry) => entry.chars().collect(),
+                        None => self.draft.clone(),
+                    };
+                    self.show(line);
+                }
+            }
+            Key::Enter => {
+                let line = self.line();
+                let repeated = self.history.last() == Some(&line);
+                if !line.trim().is_empty() && !repeated {
+                    self.history.push(line.clone());
+                }
+                self.line.clear();
+                self.draft.clear();
+                self.cursor = 0;
+                self.position = self.history.len();
+                return Event::Line(line);
+            }
+            Key::Eof if self.line.is_empty() => return Event::Eof,
+            Key::Interrupt => {
+                self.line.clear();
+                self.draft.clear();
+                self.cursor = 0;
+                self.position = self.history.len();
+                return Event::Cancelled;
+            }
+            // Needs a completer; see `complete`.
+            Key::Eof | Key::Tab | Key::Ignored => (),
+        }
+        Event::Pending
+    }
+
+    /// Completes the word before the cursor. A single candidate is filled in and
+    /// several are extended to their common prefix and returned to be listed.
+    pub fn complete(&mut self, completer: &dyn Completer) -> Vec<String> {
+        let start = self.word_start(self.cursor);
+        let prefix: String = self.line[start..self.cursor].iter().collect();
+        let context = match start.checked_sub(1).map(|dot| self.line[dot]) {
+            Some('.') => {
+                let object_start = self.word_start(start - 1);
+                CompletionContext::Property {
+                    object: self.line[object_start..start - 1].iter().collect(),
+                }
+            }
+            _ => CompletionContext::Name,
+        };
+
+        let candidates = completer.completions(&prefix, context);
+        let Some(first) = candidates.first() else {
+            return Vec::new();
+        };
+        let common = candidates.iter().fold(first.as_str(), |common, candidate| {
+            let len = common
+                .char_indices()
+                .zip(candidate.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((i, a), _)| i + a.len_utf8());
+            &common[..len]
+        });
+        for c in common.chars().skip(prefix.chars().count()) {
+            self.line.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+
+        if candidates.len() == 1 {
+            Vec::new()
+        } else {
+            candidates
+        }
+    }
+
+    // Where the identifier ending at `end` starts.
+    fn word_start(&self, end: usize) -> usize {
+        let mut start = end;
+        while start > 0 && (self.line[start - 1].is_alphanumeric() || self.line[start - 1] == '_') {
+            start -= 1;
+        }
+        start
+    }
+
+    fn show(&mut self, line: Vec<char>) {
+        self.line = line;
+        self.cursor = self.line.len();
+    }
+}
+
+/// Reads one key press from raw terminal input, or `None` at end of input.
+pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        b'\t' => Key::Tab,
+        0x1b => read_escape(input)?,
+        b if b < 0x20 => Key::Ignored,
+        b if b < 0x80 => Key::Char(b as char),
+        lead => {
+            let len = match lead {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+            let mut bytes = vec![lead];
+            for _ in 1..len {
+                match read_byte(input)? {
+                    Some(b) => bytes.push(b),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Ignored,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+// Decodes the rest of an `ESC [` sequence such as the arrow keys.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    if read_byte(input)? != Some(b'[') {
+        return Ok(Key::Ignored);
+    }
+    let key = match read_byte(input)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        // Sequences like `ESC [ 3 ~` end at the '~'.
+        Some(b'0'..=b'9') => {
+            while let Some(b) = read_byte(input)? {
+                if b == b'~' {
+                    break;
+                }
+            }
+            Key::Ignored
+        }
+        _ => Key::Ignored,
+    };
+    Ok(key)
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+#[cfg(feature = "line-editing")]
+mod terminal {
+    use std::io::{self, Write};
+    use std::process::{Command, Stdio};
+
+    use super::{read_key, Completer, Event, HistoryFile, Key, LineEditor, LineReader};
+
+    pub struct TerminalReader {
+        editor: LineEditor,
+        history: Option<HistoryFile>,
+    }
+
+    impl TerminalReader {
+        pub fn new(history: Option<HistoryFile>) -> Self {
+            let entries = history.as_ref().map(HistoryFile::load).unwrap_or_default();
+            Self {
+                editor: LineEditor::with_history(entries),
+                history,
+            }
+        }
+
+        fn redraw(&self, prompt: &str, out: &mut impl Write) -> io::Result<()> {
+            // Rewrite the whole line, then move the cursor back into place.
+            write!(out, "\r{}{}\x1b[K\r", prompt, self.editor.line())?;
+            let column = prompt.chars().count() + self.editor.cursor();
+            if column > 0 {
+                write!(out, "\x1b[{}C", column)?;
+            }
+            out.flush()
+        }
+    }
+
+    impl LineReader for TerminalReader {
+        fn read_line(
+            &mut self,
+            prompt: &str,
+            completer: &dyn Completer,
+        ) -> io::Result<Option<String>> {
+            // Raw mode only lasts while a line is read, so program output isn't affected.
+            let _raw = RawMode::enable()?;
+            let mut stdin = io::stdin().lock();
+            let mut stdout = io::stdout().lock();
+            self.redraw(prompt, &mut stdout)?;
+
+            loop {
+                let event = match read_key(&mut stdin)? {
+                    Some(Key::Tab) => {
+                        let candidates = self.editor.complete(completer);
+                        if !candidates.is_empty() {
+                            write!(stdout, "\r\n{}\r\n", candidates.join("  "))?;
+                        }
+                        Event::Pending
+                    }
+                    Some(key) => self.editor.handle(key),
+                    None => Event::Eof,
+                };
+                match event {
+                    Event::Pending => self.redraw(prompt, &mut stdout)?,
+                    Event::Cancelled => {
+                        write!(stdout, "^C\r\n")?;
+                        self.redraw(prompt, &mut stdout)?;
+                    }
+                    Event::Line(line) => {
+                        write!(stdout, "\r\n")?;
+                        return Ok(Some(line));
+                    }
+                    Event::Eof => {
+                        write!(stdout, "\r\n")?;
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for TerminalReader {
+        fn drop(&mut self) {
+            if let Some(history) = &self.history {
+                // Losing history isn't worth failing the session over.
+                let _ = history.save(self.editor.history());
+            }
+        }
+    }
+
+    // Puts the terminal in raw mode with `stty` and restores the old settings on drop.
+    struct RawMode {
+        saved: String,
+    }
+
+    impl RawMode {
+        fn enable() -> io::Result<Self> {
+            let saved = stty(&["-g"])?;
+            stty(&["raw", "-echo"])?;
+            Ok(Self {
+                saved: saved.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            let _ = stty(&[&self.saved]);
+        }
+    }
+
+    fn stty(args: &[&str]) -> io::Result<String> {
+        let output = Command::new("stty")
+            .args(args)
+            .stdin(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other("stty failed"));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Completes from a fixed list of names, plus `x` and `y` after any `object.`.
+    struct Names(&'static [&'static str]);
+
+    impl Completer for Names {
+        fn completions(&self, prefix: &str, context: CompletionContext) -> Vec<String> {
+            let names: &[&str] = match context {
+                CompletionContext::Name => self.0,
+                CompletionContext::Property { .. } => &["x", "y"],
+            };
+            names
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| name.to_string())
+                .collect()
+        }
+    }
+
+    fn type_text(editor: &mut LineEditor, text: &str) {
+        for c in text.chars() {
+            editor.handle(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_edit_in_middle_of_line() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "1 + 3");
+        editor.handle(Key::Left);
+        editor.handle(Key::Backspace);
+        editor.handle(Key::Backspace);
+        type_text(&mut editor, "* ");
+        assert_eq!(editor.line(), "1 * 3");
+        editor.handle(Key::Home);
+        type_text(&mut editor, "-");
+        editor.handle(Key::End);
+        type_text(&mut editor, "!");
+        assert_eq!(
+            editor.handle(Key::Enter),
+            Event::Line("-1 * 3!".to_string())
+        );
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn test_history_navigation() {
+        let mut editor = LineEditor::new();
+        for line in ["1", "", "2"] {
+            type_text(&mut editor, line);
+            editor.handle(Key::Enter);
+        }
+        assert_eq!(editor.history(), ["1", "2"]);
+
+        type_text(&mut editor, "dra");
+        editor.handle(Key::Up);
+        assert_eq!(editor.line(), "2");
+        editor.handle(Key::Up);
+        editor.handle(Key::Up);
+        assert_eq!(editor.line(), "1");
+        assert_eq!(editor.cursor(), 1);
+        editor.handle(Key::Down);
+        assert_eq!(editor.line(), "2");
+        editor.handle(Key::Down);
+        assert_eq!(editor.line(), "dra");
+        editor.handle(Key::Down);
+        assert_eq!(editor.line(), "dra");
+    }
+
+    #[test]
+    fn test_history_skips_repeats_and_starts_after_loaded_entries() {
+        let mut editor = LineEditor::with_history(vec!["old".to_string()]);
+        for line in ["1", "1", "2"] {
+            type_text(&mut editor, line);
+            editor.handle(Key::Enter);
+        }
+        assert_eq!(editor.history(), ["old", "1", "2"]);
+        editor.handle(Key::Up);
+        editor.handle(Key::Up);
+        editor.handle(Key::Up);
+        assert_eq!(editor.line(), "old");
+    }
+
+    fn history_file(name: &str, limit: usize) -> HistoryFile {
+        let dir = env::temp_dir().join(format!("loxide_history_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        HistoryFile {
+            path: dir.join("nested").join("history"),
+            limit,
+        }
+    }
+
+    #[test]
+    fn test_history_round_trip() {
+        let file = history_file("round_trip", 10);
+        assert!(file.load().is_empty());
+        let entries = ["1 + 2", "\"a\"", "\"a\"", "nil"].map(String::from);
+        file.save(&entries).unwrap();
+        assert_eq!(file.load(), ["1 + 2", "\"a\"", "nil"]);
+    }
+
+    #[test]
+    fn test_history_keeps_newest_entries() {
+        let file = history_file("limit", 2);
+        file.save(&["1", "2", "3"].map(String::from)).unwrap();
+        assert_eq!(fs::read_to_string(&file.path).unwrap(), "2\n3\n");
+
+        let bigger = HistoryFile { limit: 1, ..file };
+        assert_eq!(bigger.load(), ["3"]);
+    }
+
+    #[test]
+    fn test_unreadable_history_is_empty() {
+        let file = history_file("corrupt", 10);
+        fs::create_dir_all(file.path.parent().unwrap()).unwrap();
+        fs::write(&file.path, [0xff, 0xfe, b'\n']).unwrap();
+        assert!(file.load().is_empty());
+
+        // A directory where the file should be can't be read either.
+        fs::remove_file(&file.path).unwrap();
+        fs::create_dir(&file.path).unwrap();
+        assert!(file.load().is_empty());
+    }
+
+    #[test]
+    fn test_complete_unique_candidate() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "1 + pri");
+        assert!(editor.complete(&Names(&["print", "while"])).is_empty());
+        assert_eq!(editor.line(), "1 + print");
+        assert_eq!(editor.cursor(), 9);
+    }
+
+    #[test]
+    fn test_complete_lists_ambiguous_candidates() {
+        let names = Names(&["counter", "count", "class"]);
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "co");
+        assert_eq!(editor.complete(&names), ["counter", "count"]);
+        assert_eq!(editor.line(), "count");
+
+        type_text(&mut editor, " z");
+        assert!(editor.complete(&names).is_empty());
+        assert_eq!(editor.line(), "count z");
+    }
+
+    #[test]
+    fn test_complete_property_context() {
+        struct Expect;
+        impl Completer for Expect {
+            fn completions(&self, prefix: &str, context: CompletionContext) -> Vec<String> {
+                assert_eq!(prefix, "na");
+                assert_eq!(
+                    context,
+                    CompletionContext::Property {
+                        object: "point".to_string()
+                    }
+                );
+                vec!["name".to_string()]
+            }
+        }
+
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "point.na");
+        editor.complete(&Expect);
+        assert_eq!(editor.line(), "point.name");
+
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "p.");
+        assert_eq!(editor.complete(&Names(&[])), ["x", "y"]);
+    }
+
+    #[test]
+    fn test_interrupt_discards_line() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "1");
+        editor.handle(Key::Enter);
+        type_text(&mut editor, "draft");
+        editor.handle(Key::Up);
+        assert_eq!(editor.handle(Key::Interrupt), Event::Cancelled);
+        assert_eq!(editor.line(), "");
+        assert_eq!(editor.cursor(), 0);
+        assert_eq!(editor.handle(Key::Interrupt), Event::Cancelled);
+
+        // History browsing starts over, without the discarded draft.
+        editor.handle(Key::Up);
+        assert_eq!(editor.line(), "1");
+        editor.handle(Key::Down);
+        assert_eq!(editor.line(), "");
+        assert_eq!(editor.history(), ["1"]);
+    }
+
+    #[test]
+    fn test_eof_only_on_empty_line() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "x");
+        assert_eq!(editor.handle(Key::Eof), Event::Pending);
+        editor.handle(Key::Backspace);
+        assert_eq!(editor.handle(Key::Eof), Event::Eof);
+    }
+
+    #[test]
+    fn test_read_keys() {
+        let mut input: &[u8] = b"a\x1b[A\x1b[D\x1b[3~\x7f\x01\x04\r\t\xc3\xa9";
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(
+            keys,
+            [
+                Key::Char('a'),
+                Key::Up,
+                Key::Left,
+                Key::Ignored,
+                Key::Backspace,
+                Key::Home,
+                Key::Eof,
+                Key::Enter,
+                Key::Tab,
+                Key::Char('é'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plain_reader_strips_newlines() {
+        let mut reader = PlainReader::new(&b"1 + 2\r\nlast"[..]);
+        let names = Names(&[]);
+        assert_eq!(
+            reader.read_line("", &names).unwrap(),
+            Some("1 + 2".to_string())
+        );
+        assert_eq!(
+            reader.read_line("", &names).unwrap(),
+            Some("last".to_string())
+        );
+        assert_eq!(reader.read_line("", &names).unwrap(), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use crate::class::LoxInstance;
+use crate::interpreter::{Environment, Interpreter};
+use crate::parser::{Function, RuntimeError};
+use crate::value::Value;
+
+/// A function declared in Lox, together with the scope it was declared in.
+pub struct LoxFunction {
+    declaration: Arc<Function>,
+    closure: Arc<Mutex<Environment>>,
+    // A class's `init` method, which always returns the instance.
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Arc<Function>,
+        closure: Arc<Mutex<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// A copy of this method whose body sees `this` as `instance`, in a scope between
+    /// the closure and the parameters.
+    pub fn bind(&self, instance: Arc<LoxInstance>) -> LoxFunction {
+        let mut scope = Environment::with_enclosing(self.closure.clone());
+        scope.define("this", Value::Instance(instance));
+        LoxFunction::new(
+            self.declaration.clone(),
+            Arc::new(Mutex::new(scope)),
+            self.is_initializer,
+        )
+    }
+
+    /// Runs the body in a new scope enclosed by the closure, with the parameters bound
+    /// to `arguments`. The caller has already checked the argument count. An
+    /// initializer returns `this` however it finishes.
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let mut scope = Environment::with_enclosing(self.closure.clone());
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            scope.define(&param.lexeme, argument);
+        }
+
+        let value = interpreter.call_body(&self.declaration, scope)?;
+        if self.is_initializer {
+            return Ok(self.closure.lock().unwrap().lookup("this").unwrap());
+        }
+        Ok(value)
+    }
+}
+
+impl Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
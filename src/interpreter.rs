@@ -1,34 +1,1616 @@
-use std::{any::Any, rc::Rc};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
 
-use crate::parser::{Expr, RuntimeError};
+use crate::class::LoxClass;
+use crate::compiler;
+use crate::editor::{Completer, CompletionContext};
+use crate::function::LoxFunction;
+use crate::lox::LoxError;
+use crate::native::{self, NativeFunction};
+use crate::parser::{Expr, ExprId, Function, Parser, RuntimeError, Stmt};
+use crate::resolver::{Locals, Resolver};
+use crate::scanner::{self, Scanner, Token};
+use crate::style;
+use crate::value::Value;
+use crate::vm;
 
-pub fn interpret(expr: Expr) -> Result<(), RuntimeError> {
-    let val = expr.interpret()?;
-    println!("{}", stringify(val));
-    Ok(())
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    TreeWalker,
+    Vm,
 }
 
-fn stringify(val: Option<Rc<dyn Any>>) -> String {
-    match val {
-        Some(val) => {
-            if let Some(val) = val.downcast_ref::<f64>() {
-                let text = val.to_string();
-                return match text.strip_suffix(".0") {
-                    Some(t) => t.to_string(),
-                    None => text,
+/// Variable bindings for one scope, looked up by the name of an identifier token.
+/// Names not found here are looked up in the enclosing scope.
+#[derive(Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Arc<Mutex<Environment>>>,
+}
+
+impl Environment {
+    pub fn with_enclosing(enclosing: Arc<Mutex<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    /// Binds `name`, replacing any existing binding: redeclaring a variable is allowed.
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    /// The scope `distance` steps out from `environment`.
+    fn ancestor(environment: &Arc<Mutex<Environment>>, distance: usize) -> Arc<Mutex<Environment>> {
+        let mut environment = environment.clone();
+        for _ in 0..distance {
+            let enclosing = environment.lock().unwrap().enclosing.clone();
+            environment = enclosing.expect("resolved scope depth is too deep");
+        }
+        environment
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        self.lookup(&name.lexeme).ok_or_else(|| {
+            RuntimeError::new(
+                name.clone(),
+                &format!("Undefined variable '{}'.", name.lexeme),
+            )
+        })
+    }
+
+    pub(crate) fn lookup(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.values.get(name) {
+            return Some(value.clone());
+        }
+        let enclosing = self.enclosing.as_ref()?;
+        let value = enclosing.lock().unwrap().lookup(name);
+        value
+    }
+
+    /// Rebinds an existing variable in the nearest scope that declares it.
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.lock().unwrap().assign(name, value),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &format!("Undefined variable '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    /// Every name visible from this scope, including shadowed ones.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.lock().unwrap().names());
+        }
+        names
+    }
+}
+
+/// Why a statement stopped before finishing: a runtime error, or a `return` on its
+/// way back to the call it returns from.
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(e: RuntimeError) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+// Each Lox call nests several Rust calls, so deep recursion has to be stopped
+// before it overflows the native stack. At this depth an optimized build uses a
+// few megabytes, but unoptimized frames are several times bigger than a thread's
+// default stack, so run the interpreter on a thread with STACK_SIZE of stack.
+const DEFAULT_MAX_CALL_DEPTH: usize = 3000;
+
+/// The native stack an interpreter needs to reach the default call depth, even
+/// in an unoptimized build.
+pub const STACK_SIZE: usize = 256 << 20;
+
+pub struct Interpreter {
+    // Warn about comparisons that are probably bugs.
+    strict: bool,
+    backend: Backend,
+    // Arguments passed to the script, without the interpreter or script name.
+    args: Vec<String>,
+    // Whether diagnostics on stderr use ANSI colors.
+    color: bool,
+    stdout: Box<dyn Write + Send>,
+    // Runtime errors and warnings.
+    stderr: Box<dyn Write + Send>,
+    globals: Arc<Mutex<Environment>>,
+    // The innermost scope.
+    environment: Arc<Mutex<Environment>>,
+    // Where each local variable reference is declared, as worked out by the resolver
+    // for every program run so far.
+    locals: Locals,
+    // Lox calls currently running, and how many may run before a stack overflow error.
+    call_depth: usize,
+    max_call_depth: usize,
+    // Loop iterations and calls run so far, and how many may run in total.
+    steps: usize,
+    max_steps: Option<usize>,
+}
+
+pub struct InterpreterBuilder {
+    strict: bool,
+    backend: Backend,
+    args: Vec<String>,
+    color: bool,
+    max_call_depth: usize,
+    max_steps: Option<usize>,
+    // Install `clock` and the other standard natives.
+    default_natives: bool,
+    natives: Vec<NativeFunction>,
+    stdout: Box<dyn Write + Send>,
+    stderr: Box<dyn Write + Send>,
+}
+
+impl InterpreterBuilder {
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Limits how many loop iterations and calls a program may run in total before
+    /// it's stopped with a runtime error. There's no limit by default.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Defines a global function implemented in Rust. It replaces a default native
+    /// with the same name.
+    pub fn with_native(
+        mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.natives
+            .push(NativeFunction::new(name, arity, function));
+        self
+    }
+
+    pub fn without_default_natives(mut self) -> Self {
+        self.default_natives = false;
+        self
+    }
+
+    pub fn stdout(mut self, stdout: impl Write + Send + 'static) -> Self {
+        self.stdout = Box::new(stdout);
+        self
+    }
+
+    pub fn stderr(mut self, stderr: impl Write + Send + 'static) -> Self {
+        self.stderr = Box::new(stderr);
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut natives = self.natives;
+        if self.default_natives {
+            natives.splice(0..0, native::defaults());
+        }
+        let mut environment = Environment::default();
+        for native in natives {
+            let name = native.name().to_string();
+            environment.define(&name, Value::Native(Arc::new(native)));
+        }
+
+        let globals = Arc::new(Mutex::new(environment));
+        Interpreter {
+            strict: self.strict,
+            backend: self.backend,
+            args: self.args,
+            color: self.color,
+            stdout: self.stdout,
+            stderr: self.stderr,
+            environment: Arc::clone(&globals),
+            globals,
+            locals: Locals::new(),
+            call_depth: 0,
+            max_call_depth: self.max_call_depth,
+            steps: 0,
+            max_steps: self.max_steps,
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl Interpreter {
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder {
+            strict: false,
+            backend: Backend::TreeWalker,
+            args: Vec::new(),
+            color: false,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_steps: None,
+            default_natives: true,
+            natives: Vec::new(),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+        }
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        match self.backend {
+            Backend::TreeWalker => match self.execute_all(statements) {
+                Ok(()) => Ok(()),
+                Err(Unwind::Error(e)) => Err(e),
+                // The resolver rejects a return outside any function; if the program
+                // wasn't resolved, it just ends the program.
+                Err(Unwind::Return(_)) => Ok(()),
+            },
+            // An error inside a block leaves its scope pushed, so the current scope is
+            // put back for whatever runs next.
+            Backend::Vm => {
+                let environment = self.environment.clone();
+                let result = vm::run(&compiler::compile_program(statements), self);
+                self.environment = environment;
+                result.map(|_| ())
+            }
+        }
+    }
+
+    fn execute_all(&mut self, statements: &[Stmt]) -> Result<(), Unwind> {
+        for statement in statements {
+            self.execute(statement)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `source` and returns its value instead of printing it. Source that isn't a
+    /// single expression is run as a program, and its value is that of its last
+    /// statement if that's an expression statement, or else nil. Syntax errors are
+    /// returned rather than reported.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let mut scanner = Scanner::new(source.to_string(), "<eval>");
+        let tokens = scanner.scan_tokens().clone();
+        if !scanner.errors().is_empty() {
+            return Err(LoxError::Scan(scanner.errors().to_vec()));
+        }
+
+        if let Ok(expr) = Parser::new(tokens.clone()).parse_expression() {
+            let locals = Resolver::new()
+                .resolve_lone_expression(&expr)
+                .map_err(LoxError::Parse)?;
+            self.add_locals(locals);
+            return self.evaluate(&expr).map_err(LoxError::Runtime);
+        }
+        let statements = Parser::new(tokens).parse().map_err(LoxError::Parse)?;
+        let locals = Resolver::new()
+            .resolve(&statements)
+            .map_err(LoxError::Parse)?;
+        self.add_locals(locals);
+        match statements.split_last() {
+            Some((Stmt::Expression { expression }, rest)) => {
+                self.interpret(rest).map_err(LoxError::Runtime)?;
+                self.evaluate(expression).map_err(LoxError::Runtime)
+            }
+            _ => {
+                self.interpret(&statements).map_err(LoxError::Runtime)?;
+                Ok(Value::Nil)
+            }
+        }
+    }
+
+    fn execute(&mut self, statement: &Stmt) -> Result<(), Unwind> {
+        match statement {
+            Stmt::Expression { expression } => {
+                self.evaluate(expression)?;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            Stmt::Class { name, methods } => self.define_class(name, methods),
+            Stmt::Function(declaration) => self.define_function(declaration),
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
                 };
+                return Err(Unwind::Return(value));
+            }
+            Stmt::Print { expression } => {
+                let val = self.evaluate(expression)?;
+                self.print(val);
+            }
+            Stmt::Var { name, initializer } => {
+                let val = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                self.define(&name.lexeme, val);
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => loop {
+                self.step(keyword)?;
+                if !self.evaluate(condition)?.is_truthy() {
+                    break;
+                }
+                self.execute(body)?;
+            },
+            Stmt::Block { statements } => {
+                let scope = Environment::with_enclosing(self.environment.clone());
+                self.execute_block(statements, scope)?;
             }
+        }
+        Ok(())
+    }
+
+    /// Runs `statements` in `scope`, restoring the current scope afterwards even if
+    /// one of them fails.
+    fn execute_block(&mut self, statements: &[Stmt], scope: Environment) -> Result<(), Unwind> {
+        let previous = mem::replace(&mut self.environment, Arc::new(Mutex::new(scope)));
+        let result = self.execute_all(statements);
+        self.environment = previous;
+        result
+    }
+
+    /// Runs a function's body in `scope` and returns what it returns, or nil. The
+    /// current scope is restored afterwards even if the body fails.
+    pub(crate) fn call_body(
+        &mut self,
+        declaration: &Function,
+        scope: Environment,
+    ) -> Result<Value, RuntimeError> {
+        let previous = mem::replace(&mut self.environment, Arc::new(Mutex::new(scope)));
+        let result = match self.backend {
+            Backend::TreeWalker => match self.execute_all(&declaration.body) {
+                Ok(()) => Ok(Value::Nil),
+                Err(Unwind::Return(value)) => Ok(value),
+                Err(Unwind::Error(e)) => Err(e),
+            },
+            Backend::Vm => vm::run(declaration.chunk(), self),
+        };
+        self.environment = previous;
+        result
+    }
+
+    // The steps below are shared by both backends.
+
+    pub(crate) fn print(&mut self, value: Value) {
+        writeln!(self.stdout, "{}", stringify(value)).expect("Failed to write output.");
+    }
+
+    /// Binds `name` in the current scope.
+    pub(crate) fn define(&mut self, name: &str, value: Value) {
+        self.environment.lock().unwrap().define(name, value);
+    }
+
+    pub(crate) fn define_function(&mut self, declaration: &Arc<Function>) {
+        let function = LoxFunction::new(declaration.clone(), self.environment.clone(), false);
+        self.define(
+            &declaration.name.lexeme,
+            Value::Function(Arc::new(function)),
+        );
+    }
+
+    pub(crate) fn define_class(&mut self, name: &Token, methods: &[Arc<Function>]) {
+        let methods = methods
+            .iter()
+            .map(|method| {
+                let is_initializer = method.name.lexeme == "init";
+                let function =
+                    LoxFunction::new(method.clone(), self.environment.clone(), is_initializer);
+                (method.name.lexeme.clone(), Arc::new(function))
+            })
+            .collect();
+        let class = Value::Class(Arc::new(LoxClass::new(&name.lexeme, methods)));
+        self.define(&name.lexeme, class);
+    }
+
+    /// Counts a loop iteration or call, failing once there have been more than the
+    /// step limit allows.
+    pub(crate) fn step(&mut self, token: &Token) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        match self.max_steps {
+            Some(max_steps) if self.steps > max_steps => Err(RuntimeError::new(
+                token.clone(),
+                &format!("Execution exceeded {} steps.", max_steps),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn push_scope(&mut self) {
+        let scope = Environment::with_enclosing(self.environment.clone());
+        self.environment = Arc::new(Mutex::new(scope));
+    }
+
+    pub(crate) fn pop_scope(&mut self) {
+        let enclosing = self.environment.lock().unwrap().enclosing.clone();
+        self.environment = enclosing.expect("popped the global scope");
+    }
 
-            if let Some(val) = val.downcast_ref::<bool>() {
-                return val.to_string();
+    pub(crate) fn call(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let arity = match &callee {
+            Value::Function(function) => function.arity(),
+            Value::Native(native) => native.arity(),
+            Value::Class(class) => class.arity(),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Can only call functions and classes.",
+                ))
             }
+        };
 
-            if let Some(val) = val.downcast_ref::<String>() {
-                return val.to_string();
+        if arguments.len() != arity {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                &format!("Expected {} arguments but got {}.", arity, arguments.len()),
+            ));
+        }
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                &format!(
+                    "Stack overflow: exceeded {} nested calls.",
+                    self.max_call_depth
+                ),
+            ));
+        }
+
+        self.step(paren)?;
+
+        self.call_depth += 1;
+        let result = match callee {
+            Value::Class(class) => class.call(self, arguments),
+            Value::Function(function) => function.call(self, arguments),
+            Value::Native(native) => native.call(self, arguments),
+            _ => unreachable!(),
+        };
+        self.call_depth -= 1;
+        result
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match self.backend {
+            Backend::TreeWalker => expr.interpret(self),
+            Backend::Vm => vm::run(&compiler::compile(expr), self),
+        }
+    }
+
+    /// Takes on what the resolver worked out about a program, before running it.
+    pub fn add_locals(&mut self, locals: Locals) {
+        self.locals.extend(locals);
+    }
+
+    pub fn look_up_variable(&self, name: &Token, id: ExprId) -> Result<Value, RuntimeError> {
+        match self.locals.get(&id) {
+            Some(&depth) => Environment::ancestor(&self.environment, depth)
+                .lock()
+                .unwrap()
+                .get(name),
+            None => self.globals.lock().unwrap().get(name),
+        }
+    }
+
+    pub fn assign_variable(
+        &mut self,
+        name: &Token,
+        id: ExprId,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        match self.locals.get(&id) {
+            Some(&depth) => Environment::ancestor(&self.environment, depth)
+                .lock()
+                .unwrap()
+                .assign(name, value),
+            None => self.globals.lock().unwrap().assign(name, value),
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn warn(&mut self, token: &Token, message: &str) {
+        let location = format!("{}:{}", token.source, token.line);
+        let text = style::diagnostic(&location, "Warning", message, self.color);
+        writeln!(self.stderr, "{}", text).expect("Failed to write output.");
+    }
+
+    /// Reports a scan or parse error.
+    pub fn error(&mut self, source: &str, line: usize, message: &str) {
+        self.report(&format!("{}:{}", source, line), message);
+    }
+
+    /// Reports an error that isn't tied to a line, like a file that can't be read.
+    pub fn report(&mut self, location: &str, message: &str) {
+        let text = style::diagnostic(location, "Error", message, self.color);
+        writeln!(self.stderr, "{}", text).expect("Failed to write output.");
+    }
+
+    pub fn runtime_error(&mut self, error: &RuntimeError) {
+        writeln!(self.stderr, "{}", error.render(self.color)).expect("Failed to write output.");
+    }
+}
+
+// Keywords and variable names, or the fields and methods of an instance held in a
+// variable.
+impl Completer for Interpreter {
+    fn completions(&self, prefix: &str, context: CompletionContext) -> Vec<String> {
+        let names = match context {
+            CompletionContext::Name => scanner::keywords()
+                .map(str::to_string)
+                .chain(self.environment.lock().unwrap().names())
+                .collect(),
+            CompletionContext::Property { object } => {
+                let value = self.environment.lock().unwrap().lookup(&object);
+                match value {
+                    Some(Value::Instance(instance)) => {
+                        let mut names = instance.field_names();
+                        names.extend(instance.class().method_names());
+                        names
+                    }
+                    _ => Vec::new(),
+                }
             }
+        };
+        let mut names: Vec<String> = names
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
 
-            "Object does not have string representation".to_string()
+pub fn stringify(val: Value) -> String {
+    match val {
+        Value::Nil => "nil".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Number(n) => {
+            let text = n.to_string();
+            match text.strip_suffix(".0") {
+                Some(t) => t.to_string(),
+                None => text,
+            }
         }
-        None => "nil".to_string(),
+        Value::String(s) => s,
+        Value::Function(function) => format!("<fn {}>", function.name()),
+        Value::Native(_) => "<native fn>".to_string(),
+        Value::Class(class) => class.name().to_string(),
+        Value::Instance(instance) => format!("{} instance", instance.class().name()),
+        Value::Array(elements) => {
+            let elements: Vec<String> = elements.iter().cloned().map(stringify).collect();
+            format!("[{}]", elements.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::{self, Mode, Status};
+    use crate::value::Args;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // A writer the test keeps a handle to after giving it to the interpreter.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(source: &str, strict: bool) -> (String, String) {
+        run_with(Interpreter::builder().strict(strict), &[source])
+    }
+
+    // Runs each source in turn on one interpreter, like lines typed at the REPL.
+    fn run_with(builder: InterpreterBuilder, sources: &[&str]) -> (String, String) {
+        let (stdout, stderr) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter = builder
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build();
+        for source in sources {
+            lox::run(source.to_string(), "<repl>", &mut interpreter);
+        }
+        (stdout.contents(), stderr.contents())
+    }
+
+    #[test]
+    fn test_print_numbers() {
+        assert_eq!(
+            run("print 1 + 2;", false),
+            ("3\n".to_string(), String::new())
+        );
+        assert_eq!(
+            run("print 2.5;", false),
+            ("2.5\n".to_string(), String::new())
+        );
+        assert_eq!(
+            run("print -0.5 * 2;", false),
+            ("-1\n".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn test_print_other_values() {
+        assert_eq!(run("print nil;", false).0, "nil\n");
+        assert_eq!(run("print !nil;", false).0, "true\n");
+        assert_eq!(run("print \"hello\";", false).0, "hello\n");
+        assert_eq!(run("print \"n = \" + 1.5;", false).0, "n = 1.5\n");
+    }
+
+    #[test]
+    fn test_statements_run_in_order() {
+        let (stdout, stderr) = run(
+            "print 1;\n\"unused\";\nprint \"two\";\n-nil;\nprint 3;",
+            false,
+        );
+        assert_eq!(stdout, "1\ntwo\n");
+        assert_eq!(
+            stderr,
+            "<repl>:4: RuntimeError: Operand must be a number.\n"
+        );
+    }
+
+    #[test]
+    fn test_every_syntax_error_is_reported() {
+        let (stdout, stderr) = run("print 1;\nprint 2\nprint 3;\n1 +;", false);
+        assert_eq!(stdout, "");
+        assert_eq!(
+            stderr,
+            "<repl>:3: Error: Expect ';' after value.\n\
+             <repl>:4: Error: Expect expression.\n"
+        );
+        // Tokens after a complete expression are an error rather than ignored.
+        let (_, stderr) = run("print 1 2;", false);
+        assert_eq!(stderr, "<repl>:1: Error: Expect ';' after value.\n");
+    }
+
+    #[test]
+    fn test_repl_line_echoes_lone_expression() {
+        let stdout = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder().stdout(stdout.clone()).build();
+        lox::run_line("1 + 2".to_string(), "<repl>", Mode::Run, &mut interpreter);
+        lox::run_line("4;".to_string(), "<repl>", Mode::Run, &mut interpreter);
+        lox::run_line(
+            "print 5;".to_string(),
+            "<repl>",
+            Mode::Run,
+            &mut interpreter,
+        );
+        assert_eq!(stdout.contents(), "3\n5\n");
+    }
+
+    #[test]
+    fn test_repl_line_resolves_lone_expression() {
+        let stdout = SharedBuffer::default();
+        let stderr = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder()
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build();
+        let line = |source: &str, interpreter: &mut Interpreter| {
+            lox::run_line(source.to_string(), "<repl>", Mode::Run, interpreter)
+        };
+        assert_eq!(line("var x;", &mut interpreter), Status::Ok);
+        assert_eq!(line("x = 5", &mut interpreter), Status::Ok);
+        assert_eq!(line("this", &mut interpreter), Status::CompileError);
+        assert_eq!(stdout.contents(), "5\n");
+        assert_eq!(
+            stderr.contents(),
+            "<repl>:1: Error: Can't use 'this' outside of a class.\n"
+        );
+    }
+
+    #[test]
+    fn test_global_variables() {
+        let (stdout, stderr) = run(
+            "var a = 1;\nvar b;\nprint a + 2;\nprint b;\nvar a = \"again\";\nprint a;",
+            false,
+        );
+        assert_eq!(stdout, "3\nnil\nagain\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let (stdout, stderr) = run("print 1;\n\nprint missing + 1;", false);
+        assert_eq!(stdout, "1\n");
+        assert_eq!(
+            stderr,
+            "<repl>:3: RuntimeError: Undefined variable 'missing'.\n"
+        );
+
+        let (_, stderr) = run("var = 1;\nvar x 2;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: Error: Expect variable name.\n\
+             <repl>:2: Error: Expect ';' after variable declaration.\n"
+        );
+    }
+
+    #[test]
+    fn test_block_scopes() {
+        let (stdout, stderr) = run(
+            "var a = \"global a\";\n\
+             var b = \"global b\";\n\
+             {\n\
+               var a = \"outer a\";\n\
+               { var a = \"inner a\"; print a; print b; }\n\
+               print a;\n\
+             }\n\
+             print a;",
+            false,
+        );
+        assert_eq!(stdout, "inner a\nglobal b\nouter a\nglobal a\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_block_scope_ends_even_after_error() {
+        let stderr = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder().stderr(stderr.clone()).build();
+        lox::run(
+            "{ var inner = 1; -nil; }".to_string(),
+            "<repl>",
+            &mut interpreter,
+        );
+        match interpreter.eval("inner") {
+            Err(LoxError::Runtime(e)) => assert_eq!(e.message(), "Undefined variable 'inner'."),
+            _ => panic!("expected a runtime error"),
+        }
+
+        lox::run("{ print 1;".to_string(), "<repl>", &mut interpreter);
+        assert_eq!(
+            stderr.contents(),
+            "<repl>:1: RuntimeError: Operand must be a number.\n\
+             <repl>:1: Error: Expect '}' after block.\n"
+        );
+    }
+
+    #[test]
+    fn test_assignment() {
+        let (stdout, stderr) = run(
+            "var a = 1;\nvar b;\n{ a = b = a + 1; var a = 10; a = 20; }\nprint a;\nprint b;\nprint a = 3;",
+            false,
+        );
+        assert_eq!(stdout, "2\n2\n3\n");
+        assert_eq!(stderr, "");
+
+        let (_, stderr) = run("unknown = 1;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: RuntimeError: Undefined variable 'unknown'.\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let (_, stderr) = run("var a;\n(a) = 3;\n1 + a = 2;\na = 4;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:2: Error: Invalid assignment target.\n\
+             <repl>:3: Error: Invalid assignment target.\n"
+        );
+    }
+
+    #[test]
+    fn test_if_else() {
+        let (stdout, _) = run(
+            "if (1 < 2) print \"yes\"; else print \"no\";\n\
+             if (nil) print \"nil\"; else if (0) print \"zero is truthy\";\n\
+             if (false) { print 1; }",
+            false,
+        );
+        assert_eq!(stdout, "yes\nzero is truthy\n");
+
+        // The else goes with the inner if.
+        let (stdout, _) = run("if (true) if (false) print 1; else print 2;", false);
+        assert_eq!(stdout, "2\n");
+        let (stdout, _) = run("if (false) if (true) print 1; else print 2;", false);
+        assert_eq!(stdout, "");
+
+        let (_, stderr) = run("if 1 print 2;\nif (1 print 2;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: Error: Expect '(' after 'if'.\n\
+             <repl>:2: Error: Expect ')' after if condition.\n"
+        );
+    }
+
+    #[test]
+    fn test_logical_operators_skip_side_effects() {
+        let (stdout, _) = run(
+            "var a = 0;\nfalse and (a = 1);\ntrue or (a = 2);\nnil or (a = 3);\nprint a;",
+            false,
+        );
+        assert_eq!(stdout, "3\n");
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let (stdout, stderr) = run(
+            "var i = 0;\nvar total = 0;\n\
+             while (i < 5) { total = total + i; i = i + 1; }\n\
+             print total;\n\
+             while (false) print \"never\";",
+            false,
+        );
+        assert_eq!(stdout, "10\n");
+        assert_eq!(stderr, "");
+
+        // A runtime error ends the loop.
+        let (stdout, stderr) = run(
+            "var n = 2;\nwhile (n > 0) {\n  print n;\n  n = n - 1;\n  if (n == 0) -nil;\n}\nprint \"after\";",
+            false,
+        );
+        assert_eq!(stdout, "2\n1\n");
+        assert_eq!(
+            stderr,
+            "<repl>:5: RuntimeError: Operand must be a number.\n"
+        );
+
+        let (_, stderr) = run("while true print 1;", false);
+        assert_eq!(stderr, "<repl>:1: Error: Expect '(' after 'while'.\n");
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let (stdout, stderr) = run(
+            "for (var i = 0; i < 3; i = i + 1) print i;\n\
+             var j = 10;\n\
+             for (; j > 8;) j = j - 1;\n\
+             print j;\n\
+             for (j = 0; j < 2; j = j + 1) { var i = \"body\"; print i; }",
+            false,
+        );
+        assert_eq!(stdout, "0\n1\n2\n8\nbody\nbody\n");
+        assert_eq!(stderr, "");
+
+        // The loop variable is scoped to the loop.
+        let (_, stderr) = run("for (var i = 0; i < 1; i = i + 1) {}\nprint i;", false);
+        assert_eq!(stderr, "<repl>:2: RuntimeError: Undefined variable 'i'.\n");
+
+        let (_, stderr) = run("for var i = 0;\nfor (;;\n", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: Error: Expect '(' after 'for'.\n\
+             <repl>:3: Error: Expect expression.\n"
+        );
+    }
+
+    #[test]
+    fn test_functions_and_return() {
+        let (stdout, stderr) = run(
+            "fun fib(n) {\n\
+               if (n < 2) return n;\n\
+               return fib(n - 2) + fib(n - 1);\n\
+             }\n\
+             print fib(15);\n\
+             fun nothing() {}\n\
+             fun bare() { return; print \"unreachable\"; }\n\
+             print nothing();\n\
+             print bare();\n\
+             print fib;",
+            false,
+        );
+        assert_eq!(stdout, "610\nnil\nnil\n<fn fib>\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_return_unwinds_blocks_and_loops() {
+        let (stdout, stderr) = run(
+            "var scope = \"global\";\n\
+             fun find(limit) {\n\
+               for (var i = 0; i < 10; i = i + 1) {\n\
+                 var scope = \"loop\";\n\
+                 while (true) { if (i == limit) return i; i = i + 1; }\n\
+               }\n\
+             }\n\
+             print find(3);\n\
+             print scope;",
+            false,
+        );
+        assert_eq!(stdout, "3\nglobal\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_closures_capture_their_scope() {
+        let (stdout, _) = run(
+            "fun counter() {\n\
+               var count = 0;\n\
+               fun next() { count = count + 1; return count; }\n\
+               return next;\n\
+             }\n\
+             var a = counter();\n\
+             var b = counter();\n\
+             a(); a();\n\
+             print a();\n\
+             print b();",
+            false,
+        );
+        assert_eq!(stdout, "3\n1\n");
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let (_, stderr) = run("fun pair(a, b) {}\npair(1);\n\"text\"();", false);
+        assert_eq!(
+            stderr,
+            "<repl>:2: RuntimeError: Expected 2 arguments but got 1.\n"
+        );
+        let (_, stderr) = run("\"text\"();", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: RuntimeError: Can only call functions and classes.\n"
+        );
+
+        let (_, stderr) = run("fun f(a b) {}\nvar x;\nf(1 2);\nfun (x) {}", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: Error: Expect ')' after parameters.\n\
+             <repl>:3: Error: Expect ')' after arguments.\n\
+             <repl>:4: Error: Expect function name.\n"
+        );
+    }
+
+    #[test]
+    fn test_classes_construct_instances() {
+        let (stdout, stderr) = run(
+            "class Point {\n\
+               sum() { return 0; }\n\
+             }\n\
+             var a = Point();\n\
+             var b = Point();\n\
+             print Point;\n\
+             print a;\n\
+             print a == a;\n\
+             print a == b;\n\
+             Point(1, 2);",
+            false,
+        );
+        assert_eq!(stdout, "Point\nPoint instance\ntrue\nfalse\n");
+        assert_eq!(
+            stderr,
+            "<repl>:10: RuntimeError: Expected 0 arguments but got 2.\n"
+        );
+
+        let (_, stderr) = run("class {}\nvar x;\nclass A { fun f() {} }", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: Error: Expect class name.\n\
+             <repl>:3: Error: Expect method name.\n"
+        );
+    }
+
+    #[test]
+    fn test_instance_fields() {
+        let (stdout, stderr) = run(
+            "class Box {}\n\
+             var b = Box();\n\
+             b.inner = Box();\n\
+             print b.inner.size = 3;\n\
+             b.inner.size = b.inner.size + 1;\n\
+             print b.inner.size;\n\
+             print Box().size;",
+            false,
+        );
+        assert_eq!(stdout, "3\n4\n");
+        assert_eq!(
+            stderr,
+            "<repl>:7: RuntimeError: Undefined property 'size'.\n"
+        );
+
+        let (_, stderr) = run("var n = 1;\nprint n.size;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:2: RuntimeError: Only instances have properties.\n"
+        );
+        let (_, stderr) = run("\"text\".size = 1;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:1: RuntimeError: Only instances have fields.\n"
+        );
+        let (_, stderr) = run("class A {}\nA().1;\nA() = 2;", false);
+        assert_eq!(
+            stderr,
+            "<repl>:2: Error: Expect property name after '.'.\n\
+             <repl>:3: Error: Invalid assignment target.\n"
+        );
+    }
+
+    #[test]
+    fn test_methods_bind_this() {
+        let (stdout, stderr) = run(
+            "class Counter {\n\
+               add(n) { this.count = this.count + n; return this; }\n\
+               show() { print this.count; }\n\
+               later() { fun inner() { return this.count; } return inner; }\n\
+             }\n\
+             var c = Counter();\n\
+             c.count = 1;\n\
+             c.add(2).add(3).show();\n\
+             var show = c.show;\n\
+             c.count = 10;\n\
+             show();\n\
+             print c.later()();\n\
+             print c.add;\n\
+             c.show = \"shadowed\";\n\
+             print c.show;\n\
+             c.missing();",
+            false,
+        );
+        assert_eq!(stdout, "6\n10\n10\n<fn add>\nshadowed\n");
+        assert_eq!(
+            stderr,
+            "<repl>:16: RuntimeError: Undefined property 'missing'.\n"
+        );
+    }
+
+    #[test]
+    fn test_methods_keep_this_when_moved() {
+        let (stdout, stderr) = run(
+            "class Person { sayName() { print this.name; } }\n\
+             var jane = Person();\n\
+             jane.name = \"Jane\";\n\
+             var bill = Person();\n\
+             bill.name = \"Bill\";\n\
+             jane.sayName = bill.sayName;\n\
+             jane.sayName();\n\
+             class Thing {\n\
+               getCallback() {\n\
+                 fun localFunction() { print this; }\n\
+                 return localFunction;\n\
+               }\n\
+             }\n\
+             var callback = Thing().getCallback();\n\
+             callback();",
+            false,
+        );
+        assert_eq!(stdout, "Bill\nThing instance\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_init_constructs_instances() {
+        let (stdout, stderr) = run(
+            "class Point {\n\
+               init(x, y) { this.x = x; this.y = y; if (x < 0) return; this.ok = true; }\n\
+             }\n\
+             var p = Point(1, 2);\n\
+             print p.x + p.y;\n\
+             print p.ok;\n\
+             print p.init(-5, 0) == p;\n\
+             print p.x;\n\
+             var init = p.init;\n\
+             print init(3, 4);\n\
+             print Point(-1, 0).x;\n\
+             Point(1);",
+            false,
+        );
+        assert_eq!(stdout, "3\ntrue\ntrue\n-5\nPoint instance\n-1\n");
+        assert_eq!(
+            stderr,
+            "<repl>:12: RuntimeError: Expected 2 arguments but got 1.\n"
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting_is_a_syntax_error() {
+        let handle = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| {
+                let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+                let sources = [
+                    // The print's expression is one level, and each group another.
+                    format!("print {};", nested(2999)),
+                    format!("print {};", nested(3000)),
+                    format!("print {};\nprint 2;", nested(20_000)),
+                    format!("var a;\n{}1;", "a = ".repeat(20_000)),
+                    format!("print {}1;", "-".repeat(20_000)),
+                    format!("{}{}", "{".repeat(20_000), "}".repeat(20_000)),
+                ];
+                sources.map(|source| run(&source, false))
+            })
+            .unwrap();
+        let [fits, limit, parens, assignments, unary, blocks] = handle.join().unwrap();
+
+        let too_deep = "<repl>:1: Error: Expression nesting too deep.\n";
+        assert_eq!(fits, ("1\n".to_string(), String::new()));
+        assert_eq!(limit, (String::new(), too_deep.to_string()));
+        assert_eq!(parens, (String::new(), too_deep.to_string()));
+        assert_eq!(assignments.1, too_deep.replace(":1:", ":2:"));
+        assert_eq!(unary.1, too_deep);
+        assert_eq!(blocks.1, "<repl>:1: Error: Statement nesting too deep.\n");
+    }
+
+    #[test]
+    fn test_deep_recursion_is_a_runtime_error() {
+        let handle = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| {
+                run(
+                    "fun down(n) { if (n > 0) return down(n - 1); return \"bottom\"; }\n\
+                     print down(2999);\n\
+                     fun deep() { deep(); }\n\
+                     deep();",
+                    false,
+                )
+            })
+            .unwrap();
+        let (stdout, stderr) = handle.join().unwrap();
+        assert_eq!(stdout, "bottom\n");
+        assert_eq!(
+            stderr,
+            "<repl>:3: RuntimeError: Stack overflow: exceeded 3000 nested calls.\n"
+        );
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let builder = Interpreter::builder().max_call_depth(10);
+        let (stdout, stderr) = run_with(
+            builder,
+            &[
+                "fun down(n) { if (n > 0) return down(n - 1); return n; }\nprint down(9);",
+                "print down(10);",
+                "print down(3);",
+            ],
+        );
+        assert_eq!(stdout, "0\n0\n");
+        assert_eq!(
+            stderr,
+            "<repl>:1: RuntimeError: Stack overflow: exceeded 10 nested calls.\n"
+        );
+    }
+
+    #[test]
+    fn test_backends_agree_on_programs() {
+        let programs: [&[&str]; 8] = [
+            &["var a = 1; { var a = 2; { a = a + 1; print a; } print a; } print a;"],
+            &["var i = 0; while (i < 3) { print i; i = i + 1; } for (;i > 0; i = i - 1) print -i;"],
+            &["if (nil) print 1; else if (0) print 2; else print 3; if (false) print 4;"],
+            &["fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); } print fib(12);"],
+            &[
+                "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }",
+                "var c = counter(); c(); print c();",
+            ],
+            &[
+                "fun find() { for (var i = 0; ; i = i + 1) { { if (i == 4) return i; } } }",
+                "print find(); fun none() {} print none();",
+            ],
+            &[
+                "class P { init(x) { this.x = x; return; } get() { return this.x; } }",
+                "var p = P(3); print p.get(); print p.init(5) == p; print p.x; print P;",
+                "p.y;",
+            ],
+            // A runtime error inside a block leaves the REPL in the global scope.
+            &["var g = \"global\";", "{ var g = \"block\"; -g; }", "print g;"],
+        ];
+
+        for sources in programs {
+            let tree = run_with(Interpreter::builder(), sources);
+            let vm = run_with(Interpreter::builder().backend(Backend::Vm), sources);
+            assert_eq!(tree, vm, "backends disagree on {:?}", sources);
+        }
+    }
+
+    #[test]
+    fn test_variables_on_vm_backend() {
+        let stdout = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder()
+            .backend(Backend::Vm)
+            .stdout(stdout.clone())
+            .build();
+        lox::run(
+            "var answer;\nprint answer = 2 * 21;\nfun get() { return answer; }\nprint get();\n\
+             class Box { size() { return this.inner; } }\nvar b = Box();\nb.inner = answer - 40;\nprint b.size();"
+                .to_string(),
+            "<repl>",
+            &mut interpreter,
+        );
+        assert_eq!(stdout.contents(), "42\n42\n2\n");
+        match interpreter.eval("nope") {
+            Err(LoxError::Runtime(e)) => assert_eq!(e.message(), "Undefined variable 'nope'."),
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn test_runtime_error_goes_to_stderr() {
+        let (stdout, stderr) = run("print -\"a\";", false);
+        assert_eq!(stdout, "");
+        assert_eq!(
+            stderr,
+            "<repl>:1: RuntimeError: Operand must be a number.\n"
+        );
+    }
+
+    #[test]
+    fn test_eval_returns_value() {
+        let stdout = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder().stdout(stdout.clone()).build();
+        let val = interpreter.eval("1 + 2").ok().unwrap();
+        assert_eq!(stringify(val), "3");
+        let val = interpreter.eval("nil").ok().unwrap();
+        assert_eq!(val, Value::Nil);
+        assert_eq!(stdout.contents(), "");
+    }
+
+    #[test]
+    fn test_eval_returns_last_expression_statement() {
+        let stdout = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder().stdout(stdout.clone()).build();
+        assert_eq!(interpreter.eval("var x = 5;").ok(), Some(Value::Nil));
+        assert_eq!(interpreter.eval("x").ok(), Some(Value::Int(5)));
+        assert_eq!(
+            interpreter.eval("var y = 1; y + x;").ok(),
+            Some(Value::Int(6))
+        );
+        assert_eq!(interpreter.eval("x; print y;").ok(), Some(Value::Nil));
+        assert_eq!(stdout.contents(), "1\n");
+    }
+
+    #[test]
+    fn test_eval_syntax_errors() {
+        let mut interpreter = Interpreter::default();
+        match interpreter.eval("1 +") {
+            Err(e @ LoxError::Parse(_)) => {
+                assert_eq!(e.to_string(), "<eval>:1: Error: Expect expression.")
+            }
+            _ => panic!("expected a parse error"),
+        }
+        match interpreter.eval("1 @ 2") {
+            Err(LoxError::Scan(errors)) => {
+                assert_eq!(errors[0].message, "Unexpected character: @")
+            }
+            _ => panic!("expected a scan error"),
+        }
+    }
+
+    #[test]
+    fn test_eval_resolves_lone_expression() {
+        let mut interpreter = Interpreter::default();
+        match interpreter.eval("this") {
+            Err(e @ LoxError::Parse(_)) => assert_eq!(
+                e.to_string(),
+                "<eval>:1: Error: Can't use 'this' outside of a class."
+            ),
+            _ => panic!("expected a resolver error"),
+        }
+    }
+
+    #[test]
+    fn test_eval_runtime_error() {
+        let mut interpreter = Interpreter::default();
+        match interpreter.eval("-nil") {
+            Err(LoxError::Runtime(e)) => assert_eq!(e.message(), "Operand must be a number."),
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let interpreter = Interpreter::default();
+        assert!(!interpreter.is_strict());
+        assert_eq!(interpreter.backend, Backend::TreeWalker);
+        assert!(interpreter.args().is_empty());
+    }
+
+    #[test]
+    fn test_builder_backend() {
+        let stdout = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder()
+            .backend(Backend::Vm)
+            .stdout(stdout.clone())
+            .build();
+        assert_eq!(interpreter.backend, Backend::Vm);
+        lox::run("print 2 * 21;".to_string(), "<repl>", &mut interpreter);
+        assert_eq!(stdout.contents(), "42\n");
+    }
+
+    #[test]
+    fn test_default_natives() {
+        let (stdout, stderr) = run("print clock() > 0;\nprint clock;", false);
+        assert_eq!(
+            (stdout.as_str(), stderr.as_str()),
+            ("true\n<native fn>\n", "")
+        );
+
+        let builder = Interpreter::builder().without_default_natives();
+        let (stdout, stderr) = run_with(builder, &["print clock();"]);
+        assert_eq!(stdout, "");
+        assert_eq!(
+            stderr,
+            "<repl>:1: RuntimeError: Undefined variable 'clock'.\n"
+        );
+    }
+
+    #[test]
+    fn test_args_native() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let builder = Interpreter::builder()
+                .backend(backend)
+                .args(vec!["in.txt".to_string(), "-v".to_string()]);
+            let (stdout, stderr) = run_with(
+                builder,
+                &[
+                    "var a = args();\nprint a[0] + a[1 + 0];\nprint a;",
+                    "a[2];",
+                    "a[0.5];",
+                    "args[0];",
+                ],
+            );
+            assert_eq!(stdout, "in.txt-v\n[in.txt, -v]\n");
+            assert_eq!(
+                stderr,
+                "<repl>:1: RuntimeError: Array index 2 is out of range for length 2.\n\
+                 <repl>:1: RuntimeError: Array index must be an integer.\n\
+                 <repl>:1: RuntimeError: Only arrays can be indexed.\n"
+            );
+        }
+        let (stdout, _) = run("print args();", false);
+        assert_eq!(stdout, "[]\n");
+    }
+
+    #[test]
+    fn test_builder_with_native() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let builder =
+                Interpreter::builder()
+                    .backend(backend)
+                    .with_native("add", 2, |_, args| {
+                        Ok(Value::from(args.get_number(0)? + args.get_number(1)?))
+                    });
+            let (stdout, stderr) = run_with(
+                builder,
+                &["print add(1, 2.5);", "add(1);", "add(\"a\", 1);"],
+            );
+            assert_eq!(stdout, "3.5\n");
+            assert_eq!(
+                stderr,
+                "<repl>:1: RuntimeError: Expected 2 arguments but got 1.\n\
+                 [native]: RuntimeError: Expected number for argument 1 but got string.\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_builder_max_steps() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let builder = Interpreter::builder().backend(backend).max_steps(50);
+            let (stdout, stderr) = run_with(
+                builder,
+                &["var i = 0;\nwhile (true) i = i + 1;", "print i;"],
+            );
+            assert_eq!(stdout, "50\n");
+            assert_eq!(
+                stderr,
+                "<repl>:2: RuntimeError: Execution exceeded 50 steps.\n"
+            );
+
+            let builder = Interpreter::builder().backend(backend).max_steps(3);
+            let (_, stderr) = run_with(builder, &["fun f() {}\nf(); f(); f(); f();"]);
+            assert_eq!(
+                stderr,
+                "<repl>:2: RuntimeError: Execution exceeded 3 steps.\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_colored_diagnostics() {
+        let stderr = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder()
+            .color(true)
+            .stdout(SharedBuffer::default())
+            .stderr(stderr.clone())
+            .build();
+        lox::run("-nil;".to_string(), "<repl>", &mut interpreter);
+        lox::run("1 +;".to_string(), "<repl>", &mut interpreter);
+        assert_eq!(
+            stderr.contents(),
+            "\x1b[2m<repl>:1\x1b[0m: \x1b[1;31mRuntimeError\x1b[0m: Operand must be a number.\n\
+             \x1b[2m<repl>:1\x1b[0m: \x1b[1;31mError\x1b[0m: Expect expression.\n"
+        );
+    }
+
+    #[test]
+    fn test_load_command() {
+        let path = std::env::temp_dir().join(format!("loxide_load_{}.lox", std::process::id()));
+        std::fs::write(&path, "print \"loaded\" + 1;\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let (stdout, stderr) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter = Interpreter::builder()
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build();
+        let status = lox::run_command(&format!(":load {}", path), Mode::Run, &mut interpreter);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(stdout.contents(), "loaded1\n");
+
+        // Reloading runs the file again.
+        lox::run_command(&format!(":load  {} ", path), Mode::Run, &mut interpreter);
+        assert_eq!(stdout.contents(), "loaded1\nloaded1\n");
+        assert_eq!(stderr.contents(), "");
+    }
+
+    #[test]
+    fn test_load_command_errors() {
+        let stderr = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder().stderr(stderr.clone()).build();
+        let status = lox::run_command(":load /no/such/file.lox", Mode::Run, &mut interpreter);
+        assert_eq!(status, Status::CompileError);
+        lox::run_command(":load", Mode::Run, &mut interpreter);
+        lox::run_command(":lod x", Mode::Run, &mut interpreter);
+        assert_eq!(
+            stderr.contents(),
+            "/no/such/file.lox: Error: Could not read file: No such file or directory (os error 2).\n\
+             <repl>: Error: Usage: :load <path>\n\
+             <repl>: Error: Unknown command ':lod'.\n"
+        );
+    }
+
+    #[test]
+    fn test_keyword_completions() {
+        let interpreter = Interpreter::default();
+        assert_eq!(
+            interpreter.completions("f", CompletionContext::Name),
+            ["false", "for", "fun"]
+        );
+        assert_eq!(
+            interpreter.completions("whi", CompletionContext::Name),
+            ["while"]
+        );
+        assert!(interpreter
+            .completions("", CompletionContext::Name)
+            .contains(&"class".to_string()));
+        let property = CompletionContext::Property {
+            object: "point".to_string(),
+        };
+        assert!(interpreter.completions("", property).is_empty());
+    }
+
+    #[test]
+    fn test_variable_completions() {
+        let mut interpreter = Interpreter::default();
+        interpreter
+            .eval("var format = 1; var total = 2;")
+            .ok()
+            .unwrap();
+        assert_eq!(
+            interpreter.completions("f", CompletionContext::Name),
+            ["false", "for", "format", "fun"]
+        );
+        assert_eq!(
+            interpreter.completions("to", CompletionContext::Name),
+            ["total"]
+        );
+    }
+
+    #[test]
+    fn test_property_completions() {
+        let mut interpreter = Interpreter::default();
+        interpreter
+            .eval("class P { norm() {} } var point = P(); point.x = 1; point.name = 2; point.next = 3;")
+            .ok()
+            .unwrap();
+        let property = |object: &str| CompletionContext::Property {
+            object: object.to_string(),
+        };
+        assert_eq!(
+            interpreter.completions("n", property("point")),
+            ["name", "next", "norm"]
+        );
+        assert_eq!(
+            interpreter.completions("", property("point")),
+            ["name", "next", "norm", "x"]
+        );
+        assert!(interpreter.completions("", property("P")).is_empty());
+        assert!(interpreter.completions("", property("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_warning_goes_to_stderr() {
+        let (stdout, stderr) = run("print true == 1;", true);
+        assert_eq!(stdout, "false\n");
+        assert_eq!(
+            stderr,
+            "<repl>:1: Warning: Comparing values of different types (boolean and number) with '=='.\n"
+        );
+    }
+
+    #[test]
+    fn test_interpreter_and_program_move_across_threads() {
+        let mut scanner = Scanner::new("print (1 + 2) * 4;".to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        let program = Parser::new(tokens).parse().unwrap();
+        let stdout = SharedBuffer::default();
+        let mut interpreter = Interpreter::builder().stdout(stdout.clone()).build();
+
+        let handle = thread::spawn(move || {
+            assert!(interpreter.interpret(&program).is_ok());
+            interpreter.eval("\"tw\" + \"o\"").ok().unwrap()
+        });
+        assert_eq!(handle.join().unwrap(), Value::from("two"));
+        assert_eq!(stdout.contents(), "12\n");
     }
 }
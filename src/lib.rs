@@ -0,0 +1,14 @@
+pub mod class;
+pub mod compiler;
+pub mod editor;
+pub mod function;
+pub mod interpreter;
+pub mod lox;
+pub mod native;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod style;
+pub mod value;
+pub mod vm;
+pub mod watch;
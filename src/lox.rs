@@ -1,58 +1,353 @@
 use std::fs::read_to_string;
 use std::io;
-use std::io::Write;
 
-use crate::interpreter;
-use crate::parser::Parser;
-use crate::scanner::Scanner;
+use std::fmt::Display;
 
-pub fn run_file(path: &str) -> io::Result<()> {
-    let source = read_to_string(path)?;
-    run(source);
+use crate::editor::{self, HistoryFile};
+use crate::interpreter::{stringify, Interpreter};
+use crate::parser::{ParseError, Parser, RuntimeError, Stmt};
+use crate::resolver::{Locals, Resolver};
+use crate::scanner::{ScanError, Scanner, Token};
+use crate::style;
+use crate::value::Value;
 
-    Ok(())
+#[derive(Clone)]
+pub enum LoxError {
+    Scan(Vec<ScanError>),
+    Parse(Vec<ParseError>),
+    Runtime(RuntimeError),
+}
+
+impl Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxError::Scan(errors) => {
+                let lines: Vec<String> = errors
+                    .iter()
+                    .map(|e| format_error(&e.source, e.line, &e.message))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            LoxError::Parse(errors) => {
+                let lines: Vec<String> = errors
+                    .iter()
+                    .map(|e| format_error(&e.source, e.line, &e.message))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            LoxError::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Outcome of running a piece of source, following the sysexits convention the
+/// book uses for the process exit code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    // A scan or parse error; nothing was run.
+    CompileError,
+    RuntimeError,
+}
+
+impl Status {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::CompileError => 65,
+            Status::RuntimeError => 70,
+        }
+    }
 }
 
-pub fn run_prompt() -> io::Result<()> {
-    let stdin = io::stdin();
+/// What to do with source text once it's read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Run,
+    // Print the token stream and stop.
+    Tokens,
+    // Print the parse tree and stop.
+    Ast,
+    // Report syntax errors without running anything.
+    Check,
+}
 
-    loop {
-        let mut line = String::new();
+pub fn run_file(path: &str, mode: Mode, interpreter: &mut Interpreter) -> io::Result<Status> {
+    let source = read_to_string(path)?;
+    Ok(execute(source, path, mode, interpreter))
+}
 
-        print!("> ");
-        io::stdout().flush()?;
-        // EOF if bytes = 0
-        let bytes = stdin.read_line(&mut line)?;
+/// Handles several files in order with one interpreter, so that earlier files can
+/// define functions and classes for later ones. Unreadable files are reported. When
+/// running, every file is compiled first and nothing runs unless all of them are
+/// clean; a runtime error stops the files after it.
+pub fn run_files(paths: &[String], mode: Mode, interpreter: &mut Interpreter) -> Status {
+    let mut status = Status::Ok;
+    let mut sources = Vec::new();
+    for path in paths {
+        match read_to_string(path) {
+            Ok(source) => sources.push((path, source)),
+            Err(e) => {
+                interpreter.report(path, &format!("Could not read file: {}.", e));
+                status = Status::CompileError;
+            }
+        }
+    }
 
-        line = line.trim().to_string();
+    if mode != Mode::Run {
+        // Nothing runs, so each file is handled on its own and all are reported.
+        for (path, source) in sources {
+            if execute(source, path, mode, interpreter) != Status::Ok {
+                status = Status::CompileError;
+            }
+        }
+        return status;
+    }
 
-        if bytes == 0 || line == "exit" {
+    let mut programs = Vec::new();
+    for (path, source) in sources {
+        match compile(source, path) {
+            Ok(program) => programs.push(program),
+            Err(errors) => {
+                report(&errors, interpreter);
+                status = Status::CompileError;
+            }
+        }
+    }
+    if status != Status::Ok {
+        return status;
+    }
+
+    for program in programs {
+        let status = run_program(program, interpreter);
+        if status != Status::Ok {
+            return status;
+        }
+    }
+    Status::Ok
+}
+
+/// Scans, parses and resolves each file without running anything, writing every
+/// error found to stderr. No interpreter is needed.
+pub fn check_files(paths: &[String], color: bool) -> Status {
+    let mut status = Status::Ok;
+    for path in paths {
+        let errors = match read_to_string(path) {
+            Ok(source) => match compile(source, path) {
+                Ok(_) => continue,
+                Err(errors) => errors,
+            },
+            Err(e) => {
+                let message = format!("Could not read file: {}.", e);
+                eprintln!("{}", style::diagnostic(path, "Error", &message, color));
+                status = Status::CompileError;
+                continue;
+            }
+        };
+        for e in errors {
+            let location = format!("{}:{}", e.source, e.line);
+            eprintln!(
+                "{}",
+                style::diagnostic(&location, "Error", &e.message, color)
+            );
+        }
+        status = Status::CompileError;
+    }
+    status
+}
+
+pub fn run_prompt(
+    mode: Mode,
+    history: Option<HistoryFile>,
+    interpreter: &mut Interpreter,
+) -> io::Result<()> {
+    let mut reader = editor::stdin_reader(history);
+
+    while let Some(line) = reader.read_line("> ", interpreter)? {
+        let line = line.trim().to_string();
+        if line == "exit" {
             break;
         }
 
-        run(line);
+        // Errors are reported per line; the session carries on.
+        if line.starts_with(':') {
+            run_command(&line, mode, interpreter);
+        } else {
+            run_line(line, "<repl>", mode, interpreter);
+        }
     }
 
     Ok(())
 }
 
-pub fn run(source: String) {
-    let mut sc = Scanner::new(source);
-    let tokens = sc.scan_tokens();
-    let mut parser = Parser::new(tokens.clone());
+/// Runs a REPL meta-command such as `:load path/to/file.lox`.
+pub fn run_command(line: &str, mode: Mode, interpreter: &mut Interpreter) -> Status {
+    let (command, argument) = match line.split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line, ""),
+    };
 
-    if let Some(expr) = parser.parse() {
-        match interpreter::interpret(expr) {
-            Ok(_) => (),
-            Err(e) => eprintln!("{}", e),
-        };
+    match command {
+        ":load" if argument.is_empty() => {
+            interpreter.report("<repl>", "Usage: :load <path>");
+            Status::CompileError
+        }
+        ":load" => match run_file(argument, mode, interpreter) {
+            Ok(status) => status,
+            Err(e) => {
+                interpreter.report(argument, &format!("Could not read file: {}.", e));
+                Status::CompileError
+            }
+        },
+        _ => {
+            interpreter.report("<repl>", &format!("Unknown command '{}'.", command));
+            Status::CompileError
+        }
     }
 }
 
-pub fn error(line: usize, message: &str) {
-    report(line, "", message);
+/// Runs a line typed at the REPL. A lone expression without a trailing `;` has its
+/// value printed; anything else is run like a script.
+pub fn run_line(source: String, name: &str, mode: Mode, interpreter: &mut Interpreter) -> Status {
+    if mode == Mode::Run {
+        let mut sc = Scanner::new(source.clone(), name);
+        let tokens = sc.scan_tokens().clone();
+        if sc.errors().is_empty() {
+            if let Ok(expression) = Parser::new(tokens).parse_expression() {
+                let statements = vec![Stmt::Print { expression }];
+                return match Resolver::new().resolve(&statements) {
+                    Ok(locals) => run_program(Program { statements, locals }, interpreter),
+                    Err(errors) => {
+                        report(&errors, interpreter);
+                        Status::CompileError
+                    }
+                };
+            }
+        }
+    }
+    execute(source, name, mode, interpreter)
+}
+
+pub fn execute(source: String, name: &str, mode: Mode, interpreter: &mut Interpreter) -> Status {
+    match mode {
+        Mode::Run => run(source, name, interpreter),
+        Mode::Tokens => print_tokens(source, name, interpreter),
+        Mode::Ast => print_ast(source, name, interpreter),
+        Mode::Check => check(source, name, interpreter),
+    }
+}
+
+pub fn print_ast(source: String, name: &str, interpreter: &mut Interpreter) -> Status {
+    match compile(source, name) {
+        Ok(program) => {
+            for statement in program.statements {
+                println!("{}", statement);
+            }
+            Status::Ok
+        }
+        Err(errors) => {
+            report(&errors, interpreter);
+            Status::CompileError
+        }
+    }
+}
+
+/// Scans, parses and resolves without running anything, reporting every error
+/// found. The interpreter is only used to report errors.
+pub fn check(source: String, name: &str, interpreter: &mut Interpreter) -> Status {
+    match compile(source, name) {
+        Ok(_) => Status::Ok,
+        Err(errors) => {
+            report(&errors, interpreter);
+            Status::CompileError
+        }
+    }
+}
+
+/// Source that scanned, parsed and resolved cleanly, ready to run.
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    pub locals: Locals,
+}
+
+/// The front end shared by every mode: scans, parses and resolves `source` without
+/// running anything. Parsing carries on after scan errors, so every error found is
+/// returned, in the order found.
+pub fn compile(source: String, name: &str) -> Result<Program, Vec<ParseError>> {
+    let mut sc = Scanner::new(source, name);
+    let tokens = sc.scan_tokens().clone();
+    let mut errors: Vec<ParseError> = sc.errors().iter().cloned().map(ParseError::from).collect();
+
+    let statements = match Parser::new(tokens).parse() {
+        Ok(statements) if errors.is_empty() => statements,
+        Ok(_) => return Err(errors),
+        Err(parse_errors) => {
+            errors.extend(parse_errors);
+            return Err(errors);
+        }
+    };
+    let locals = Resolver::new().resolve(&statements)?;
+    Ok(Program { statements, locals })
+}
+
+fn report(errors: &[ParseError], interpreter: &mut Interpreter) {
+    for e in errors {
+        interpreter.error(&e.source, e.line, &e.message);
+    }
+}
+
+pub fn print_tokens(source: String, name: &str, interpreter: &mut Interpreter) -> Status {
+    let mut sc = Scanner::new(source, name);
+    for token in sc.scan_tokens() {
+        println!("{}", format_token(token));
+    }
+    for e in sc.errors() {
+        interpreter.error(&e.source, e.line, &e.message);
+    }
+
+    if sc.errors().is_empty() {
+        Status::Ok
+    } else {
+        Status::CompileError
+    }
+}
+
+/// One line of `--tokens` output: line, type, lexeme and literal in columns.
+pub fn format_token(token: &Token) -> String {
+    let line = format!(
+        "{:>4} {:<13} {:<12}",
+        token.line,
+        token.token_type.to_string(),
+        token.lexeme
+    );
+    match &token.literal {
+        Some(Value::String(s)) => format!("{} {:?}", line, s),
+        Some(literal) => format!("{} {}", line, stringify(literal.clone())),
+        None => line.trim_end().to_string(),
+    }
+}
+
+pub fn run(source: String, name: &str, interpreter: &mut Interpreter) -> Status {
+    match compile(source, name) {
+        Ok(program) => run_program(program, interpreter),
+        Err(errors) => {
+            report(&errors, interpreter);
+            Status::CompileError
+        }
+    }
+}
+
+fn run_program(program: Program, interpreter: &mut Interpreter) -> Status {
+    interpreter.add_locals(program.locals);
+    match interpreter.interpret(&program.statements) {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            interpreter.runtime_error(&e);
+            Status::RuntimeError
+        }
+    }
 }
 
-pub fn report(line: usize, location: &str, message: &str) {
-    eprintln!("[line {}] Error {}: {}", line, location, message);
+fn format_error(source: &str, line: usize, message: &str) -> String {
+    style::diagnostic(&format!("{}:{}", source, line), "Error", message, false)
 }
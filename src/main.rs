@@ -1,20 +1,374 @@
-use lox::*;
+use loxide::editor::HistoryFile;
+use loxide::interpreter::{Backend, Interpreter, STACK_SIZE};
+use loxide::lox::*;
+use loxide::style::ColorChoice;
+use loxide::watch::{watched_files, PollingWatcher, Watcher};
 use std::env;
+use std::io::{self, IsTerminal};
+use std::path::Path;
 use std::process::exit;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-mod interpreter;
-mod lox;
-mod parser;
-mod scanner;
+const USAGE: &str = "Usage: loxide [options] [script [args...]]";
+
+const HELP: &str = "\
+Runs a Lox script, or starts a REPL when no script is given. Arguments after
+the script path are passed through to the script. Preludes and the script are
+all checked before any of them runs.
+
+Options:
+  -e, --eval <source>  Run source instead of a script; repeat to add lines
+      --prelude <file> Run file first, sharing its globals; can be repeated
+      --strict         Warn about comparisons that are probably bugs
+      --backend=tree   Evaluate with the tree-walking interpreter (default)
+      --backend=vm     Evaluate with the bytecode VM
+      --tokens         Print the token stream instead of running
+      --ast            Print the parse tree instead of running
+      --check          Report static errors in every given file without running
+      --color=WHEN     Color diagnostics: auto (default), always or never
+      --no-color       Same as --color=never
+      --no-history     Don't load or save REPL history
+      --watch          Rerun the script whenever it changes
+  -h, --help           Print this help
+  -V, --version        Print the version
+
+Color is only used on a terminal and is turned off by setting NO_COLOR.
+REPL history is kept in $XDG_DATA_HOME/loxide/history or ~/.loxide_history,
+up to LOXIDE_HISTORY_SIZE entries (default 1000).
+
+Exit codes:
+  0   Success
+  64  Bad command line
+  65  Syntax error
+  70  Runtime error";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(Config),
+    Help,
+    Version,
+}
+
+#[derive(Debug, PartialEq)]
+struct Config {
+    strict: bool,
+    backend: Backend,
+    mode: Mode,
+    color: ColorChoice,
+    // Save REPL history between sessions.
+    history: bool,
+    // Rerun the script whenever it changes.
+    watch: bool,
+    evals: Vec<String>,
+    // Files run before the script, -e source or REPL, in the same interpreter.
+    preludes: Vec<String>,
+    path: Option<String>,
+    // Everything after the script path. With --check these are more files to check.
+    args: Vec<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut config = Config {
+        strict: false,
+        backend: Backend::TreeWalker,
+        mode: Mode::Run,
+        color: ColorChoice::Auto,
+        history: true,
+        watch: false,
+        evals: Vec::new(),
+        preludes: Vec::new(),
+        path: None,
+        args: Vec::new(),
+    };
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-e" | "--eval" => match args.next() {
+                Some(source) => config.evals.push(source),
+                None => return Err(format!("{} needs a source argument.", arg)),
+            },
+            "--prelude" => match args.next() {
+                Some(path) => config.preludes.push(path),
+                None => return Err(format!("{} needs a file argument.", arg)),
+            },
+            "--strict" => config.strict = true,
+            "--backend=tree" => config.backend = Backend::TreeWalker,
+            "--backend=vm" => config.backend = Backend::Vm,
+            "--color=auto" => config.color = ColorChoice::Auto,
+            "--color=always" => config.color = ColorChoice::Always,
+            "--color=never" | "--no-color" => config.color = ColorChoice::Never,
+            "--no-history" => config.history = false,
+            "--watch" => config.watch = true,
+            "--tokens" | "--ast" | "--check" if config.mode != Mode::Run => {
+                return Err("Only one of --tokens, --ast and --check can be given.".to_string())
+            }
+            "--tokens" => config.mode = Mode::Tokens,
+            "--ast" => config.mode = Mode::Ast,
+            "--check" => config.mode = Mode::Check,
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option '{}'.", arg))
+            }
+            _ => {
+                config.path = Some(arg);
+                break;
+            }
+        }
+    }
+    config.args = args.collect();
+
+    if !config.evals.is_empty() && config.path.is_some() {
+        return Err("A script path can't be combined with -e.".to_string());
+    }
+    if config.watch && config.path.is_none() {
+        return Err("--watch needs a script path.".to_string());
+    }
+    Ok(Command::Run(config))
+}
+
+fn interpreter(config: &Config) -> Interpreter {
+    let color = config.color.enabled(io::stderr().is_terminal());
+    Interpreter::builder()
+        .strict(config.strict)
+        .backend(config.backend)
+        .color(color)
+        .args(config.args.clone())
+        .build()
+}
+
+fn run(config: Config) -> ! {
+    // Every path is a file to check, rather than arguments for the first one. Nothing
+    // runs, so no interpreter is needed.
+    if let (Mode::Check, Some(path)) = (config.mode, &config.path) {
+        let paths = [
+            &config.preludes[..],
+            std::slice::from_ref(path),
+            &config.args,
+        ]
+        .concat();
+        let color = config.color.enabled(io::stderr().is_terminal());
+        exit(check_files(&paths, color).exit_code())
+    }
+
+    let mut interpreter = interpreter(&config);
+
+    // A script is run together with the preludes below, so that nothing runs
+    // unless every file is clean.
+    if config.path.is_none() && !config.preludes.is_empty() {
+        let status = run_files(&config.preludes, config.mode, &mut interpreter);
+        if status != Status::Ok {
+            exit(status.exit_code())
+        }
+    }
+
+    if !config.evals.is_empty() {
+        let source = config.evals.join("\n");
+        let status = run_line(source, "<eval>", config.mode, &mut interpreter);
+        exit(status.exit_code())
+    }
+
+    if let (true, Some(path)) = (config.watch, &config.path) {
+        watch(&config, path)
+    }
+
+    match config.path {
+        None => {
+            let history = match config.history {
+                true => HistoryFile::default_location(),
+                false => None,
+            };
+            run_prompt(config.mode, history, &mut interpreter).expect("Shell error");
+            exit(0)
+        }
+        Some(path) => {
+            let paths = [config.preludes, vec![path]].concat();
+            exit(run_files(&paths, config.mode, &mut interpreter).exit_code())
+        }
+    }
+}
+
+// Reruns the script from a fresh interpreter whenever it changes, so no state
+// carries over. Errors are printed and the watcher keeps waiting.
+fn watch(config: &Config, path: &str) -> ! {
+    let paths = [&config.preludes[..], &[path.to_string()]].concat();
+    let files = paths
+        .iter()
+        .flat_map(|path| watched_files(Path::new(path)))
+        .collect();
+    let mut watcher = PollingWatcher::new(files, Duration::from_millis(300));
+
+    loop {
+        // Clear the screen and move the cursor to the top left.
+        print!("\x1b[2J\x1b[H");
+        println!("[{}] {}", clock_time(SystemTime::now()), path);
+
+        let mut interpreter = interpreter(config);
+        run_files(&paths, config.mode, &mut interpreter);
+        watcher
+            .wait_for_change()
+            .expect("Error watching source file.");
+    }
+}
+
+fn clock_time(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) % 86_400;
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        1 => run_prompt().expect("Shell error"),
-        2 => run_file(&args[1]).expect("Error reading source file."),
-        _ => {
-            println!("Usage: loxide [script]");
+    match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(config)) => {
+            // Lox calls and nested syntax recurse on the native stack, which needs to
+            // be bigger than the main thread's.
+            let runner = thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn(move || run(config))
+                .expect("failed to spawn the interpreter thread");
+            if runner.join().is_err() {
+                exit(101)
+            }
+        }
+        Ok(Command::Help) => println!("{}\n\n{}", USAGE, HELP),
+        Ok(Command::Version) => println!("loxide {}", env!("CARGO_PKG_VERSION")),
+        Err(message) => {
+            eprintln!("loxide: {}", message);
+            eprintln!("{}", USAGE);
+            eprintln!("Run 'loxide --help' for more information.");
             exit(64)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    fn config(args: &[&str]) -> Config {
+        match parse(args) {
+            Ok(Command::Run(config)) => config,
+            other => panic!("expected a run config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_defaults_start_repl() {
+        let config = config(&[]);
+        assert_eq!(config.path, None);
+        assert_eq!(config.mode, Mode::Run);
+        assert_eq!(config.backend, Backend::TreeWalker);
+        assert_eq!(config.color, ColorChoice::Auto);
+        assert!(config.history);
+        assert!(!config.strict);
+    }
+
+    #[test]
+    fn test_no_history() {
+        assert!(!config(&["--no-history"]).history);
+    }
+
+    #[test]
+    fn test_watch() {
+        let config = config(&["--watch", "main.lox"]);
+        assert!(config.watch);
+        assert_eq!(
+            parse(&["--watch"]),
+            Err("--watch needs a script path.".to_string())
+        );
+        assert_eq!(
+            parse(&["--watch", "-e", "1"]),
+            Err("--watch needs a script path.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_clock_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(3 * 86_400 + 13 * 3600 + 5 * 60 + 9);
+        assert_eq!(clock_time(time), "13:05:09 UTC");
+    }
+
+    #[test]
+    fn test_color_flags() {
+        assert_eq!(config(&["--color=always"]).color, ColorChoice::Always);
+        assert_eq!(config(&["--color=never"]).color, ColorChoice::Never);
+        assert_eq!(config(&["--no-color"]).color, ColorChoice::Never);
+        assert_eq!(
+            config(&["--color=always", "--color=auto"]).color,
+            ColorChoice::Auto
+        );
+    }
+
+    #[test]
+    fn test_flags_before_script() {
+        let config = config(&["--strict", "--backend=vm", "--ast", "main.lox"]);
+        assert!(config.strict);
+        assert_eq!(config.backend, Backend::Vm);
+        assert_eq!(config.mode, Mode::Ast);
+        assert_eq!(config.path.as_deref(), Some("main.lox"));
+    }
+
+    #[test]
+    fn test_arguments_after_script_are_not_flags() {
+        let config = config(&["main.lox", "--help", "-e", "x"]);
+        assert_eq!(config.path.as_deref(), Some("main.lox"));
+        assert_eq!(config.args, ["--help", "-e", "x"]);
+        assert!(config.evals.is_empty());
+    }
+
+    #[test]
+    fn test_help_and_version() {
+        assert_eq!(parse(&["--help"]), Ok(Command::Help));
+        assert_eq!(parse(&["--strict", "-h"]), Ok(Command::Help));
+        assert_eq!(parse(&["--version"]), Ok(Command::Version));
+        assert_eq!(parse(&["-V"]), Ok(Command::Version));
+    }
+
+    #[test]
+    fn test_eval_sources_collect() {
+        let config = config(&["-e", "1 +", "--eval", "2"]);
+        assert_eq!(config.evals, ["1 +", "2"]);
+    }
+
+    #[test]
+    fn test_preludes_collect() {
+        let config = config(&["--prelude", "a.lox", "--prelude", "b.lox", "main.lox"]);
+        assert_eq!(config.preludes, ["a.lox", "b.lox"]);
+        assert_eq!(config.path.as_deref(), Some("main.lox"));
+        assert_eq!(
+            parse(&["--prelude"]),
+            Err("--prelude needs a file argument.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        assert_eq!(
+            parse(&["--bogus"]),
+            Err("Unknown option '--bogus'.".to_string())
+        );
+        assert_eq!(parse(&["-x"]), Err("Unknown option '-x'.".to_string()));
+        assert_eq!(
+            parse(&["-e"]),
+            Err("-e needs a source argument.".to_string())
+        );
+        assert_eq!(
+            parse(&["--tokens", "--check"]),
+            Err("Only one of --tokens, --ast and --check can be given.".to_string())
+        );
+        assert_eq!(
+            parse(&["-e", "1", "main.lox"]),
+            Err("A script path can't be combined with -e.".to_string())
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::interpreter::Interpreter;
+use crate::parser::RuntimeError;
+use crate::value::Value;
+
+/// The Rust side of a native function. It's given the arguments once their number
+/// has been checked against the arity.
+pub type NativeFn = dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError> + Send + Sync;
+
+/// A function implemented in Rust that Lox code can call like any other.
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        (self.function)(interpreter, &arguments)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// The natives every interpreter starts with, unless it's built without them.
+pub fn defaults() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("clock", 0, |_, _| {
+            let elapsed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| RuntimeError::native(&e.to_string()))?;
+            Ok(Value::Number(elapsed.as_secs_f64()))
+        }),
+        // The arguments passed to the script, which are none in the REPL and with -e.
+        NativeFunction::new("args", 0, |interpreter, _| {
+            let args = interpreter
+                .args()
+                .iter()
+                .map(|arg| Value::from(arg.as_str()));
+            Ok(Value::Array(Arc::new(args.collect())))
+        }),
+    ]
+}
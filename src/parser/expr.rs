@@ -1,209 +1,882 @@
-use std::{any::Any, fmt::Display, rc::Rc};
+use std::fmt::Display;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use super::printer::{self, Piece};
+use crate::interpreter::{stringify, Interpreter};
 use crate::scanner::{Token, TokenType};
+use crate::style;
+use crate::value::Value;
 
-macro_rules! parenthesize {
-    ( $name:expr, $($e:expr), *) => {{
-        let mut result = String::from("(");
-        result.push_str(&$name.to_string());
-        $(
-            result.push(' ');
-            result.push_str(&$e.to_string());
-        )*
-        result.push(')');
-        result
-    }};
+/// Identifies a variable reference so the resolver can record which scope it refers
+/// to. Ids are never reused, even across programs run by the same interpreter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    pub fn next() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        ExprId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 pub enum Expr {
+    Assign {
+        id: ExprId,
+        name: Token,
+        value: Box<Expr>,
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
+    Call {
+        callee: Box<Expr>,
+        // The closing parenthesis, for the line of errors raised by the call.
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
     Grouping {
         expression: Box<Expr>,
     },
+    Index {
+        object: Box<Expr>,
+        // The closing bracket, for the line of errors raised by indexing.
+        bracket: Token,
+        index: Box<Expr>,
+    },
     Literal {
-        value: Option<Rc<dyn Any>>,
+        value: Value,
+    },
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This {
+        id: ExprId,
+        keyword: Token,
     },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
+    Variable {
+        id: ExprId,
+        name: Token,
+    },
+}
+
+// Statements keep their expressions around to run them again, so trees live long
+// enough to be dropped, and the default drop glue would recurse as deep as the tree.
+// Instead each node's children are detached and dropped from a flat list.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.detach_children(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.detach_children(&mut pending);
+        }
+    }
+}
+
+// Pending work for `Expr::interpret`. Evaluation walks the tree with an explicit stack
+// instead of recursing so that very deep expressions can't overflow the Rust stack.
+enum Work<'a> {
+    Evaluate(&'a Expr),
+    // The left operand has been evaluated; evaluate the right one next.
+    BinaryRight {
+        operator: &'a Token,
+        right: &'a Expr,
+    },
+    Binary(&'a Token),
+    Unary(&'a Token),
+    // The value has been evaluated and is left on the stack as the result.
+    Assign {
+        name: &'a Token,
+        id: ExprId,
+    },
+    // The left operand has been evaluated; it's the result unless it doesn't decide
+    // the outcome, in which case the right operand is.
+    LogicalRight {
+        operator: &'a Token,
+        right: &'a Expr,
+    },
+    // The callee and `count` arguments have been evaluated, in that order.
+    Call {
+        paren: &'a Token,
+        count: usize,
+    },
+    // The object has been evaluated.
+    Get(&'a Token),
+    // The object and then the index have been evaluated.
+    Index(&'a Token),
+    // The object and then the value have been evaluated.
+    Set(&'a Token),
 }
 
 impl Expr {
-    pub fn interpret(self) -> Result<Option<Rc<dyn Any>>, RuntimeError> {
-        match self {
-            Expr::Literal { value } => Ok(value),
-            Expr::Grouping { expression } => expression.interpret(),
-            Expr::Unary { operator, right } => match operator.token_type {
-                TokenType::Bang => {
-                    let val = right.interpret()?;
-                    Ok(Some(Rc::new(!Expr::is_truthy(val))))
-                }
-                TokenType::Minus => {
-                    let val =
-                        (*right).try_convert::<f64>(&operator, "Operand must be a number.")?;
-                    Ok(Some(Rc::new(-val)))
-                }
-                _ => Err(RuntimeError {
-                    message: "Invalid unary operator.".to_string(),
-                    token: operator,
-                }),
-            },
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => match operator.token_type {
-                TokenType::Minus
-                | TokenType::Slash
-                | TokenType::Star
-                | TokenType::Greater
-                | TokenType::GreaterEqual
-                | TokenType::Less
-                | TokenType::LessEqual => {
-                    let (l, r) = (
-                        (*left).try_convert::<f64>(&operator, "Operands must be numbers.")?,
-                        (*right).try_convert::<f64>(&operator, "Operands must be numbers.")?,
-                    );
+    pub fn interpret(&self, interpreter: &mut Interpreter) -> Result<Value, RuntimeError> {
+        let mut work = vec![Work::Evaluate(self)];
+        let mut values: Vec<Value> = Vec::new();
 
-                    match operator.token_type {
-                        TokenType::Minus => Ok(Some(Rc::new(l - r))),
-                        TokenType::Slash => Ok(Some(Rc::new(l / r))),
-                        TokenType::Star => Ok(Some(Rc::new(l * r))),
-                        TokenType::Greater => Ok(Some(Rc::new(l > r))),
-                        TokenType::GreaterEqual => Ok(Some(Rc::new(l >= r))),
-                        TokenType::Less => Ok(Some(Rc::new(l < r))),
-                        TokenType::LessEqual => Ok(Some(Rc::new(l <= r))),
-                        _ => unreachable!(),
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Evaluate(expr) => match expr {
+                    Expr::Literal { value } => values.push(value.clone()),
+                    // `this` is bound like a variable in a scope around each method.
+                    Expr::Variable { id, name } | Expr::This { id, keyword: name } => {
+                        values.push(interpreter.look_up_variable(name, *id)?)
                     }
-                }
-                TokenType::Plus => {
-                    let err = RuntimeError {
-                        message: "Operands must be two numbers or two strings.".to_string(),
-                        token: operator,
-                    };
-                    let left = left.interpret()?.ok_or(err.clone())?;
-                    let right = right.interpret()?.ok_or(err.clone())?;
-
-                    if let (Some(l), Some(r)) =
-                        (left.downcast_ref::<f64>(), right.downcast_ref::<f64>())
+                    Expr::Assign { id, name, value } => {
+                        work.push(Work::Assign { name, id: *id });
+                        work.push(Work::Evaluate(value));
+                    }
+                    Expr::Call {
+                        callee,
+                        paren,
+                        arguments,
+                    } => {
+                        work.push(Work::Call {
+                            paren,
+                            count: arguments.len(),
+                        });
+                        work.extend(arguments.iter().rev().map(Work::Evaluate));
+                        work.push(Work::Evaluate(callee));
+                    }
+                    Expr::Get { object, name } => {
+                        work.push(Work::Get(name));
+                        work.push(Work::Evaluate(object));
+                    }
+                    Expr::Grouping { expression } => work.push(Work::Evaluate(expression)),
+                    Expr::Index {
+                        object,
+                        bracket,
+                        index,
+                    } => {
+                        work.push(Work::Index(bracket));
+                        work.push(Work::Evaluate(index));
+                        work.push(Work::Evaluate(object));
+                    }
+                    Expr::Set {
+                        object,
+                        name,
+                        value,
+                    } => {
+                        work.push(Work::Set(name));
+                        work.push(Work::Evaluate(value));
+                        work.push(Work::Evaluate(object));
+                    }
+                    Expr::Logical {
+                        left,
+                        operator,
+                        right,
+                    } => {
+                        work.push(Work::LogicalRight { operator, right });
+                        work.push(Work::Evaluate(left));
+                    }
+                    Expr::Unary { operator, right } => {
+                        work.push(Work::Unary(operator));
+                        work.push(Work::Evaluate(right));
+                    }
+                    Expr::Binary {
+                        left,
+                        operator,
+                        right,
+                    } => {
+                        work.push(Work::BinaryRight { operator, right });
+                        work.push(Work::Evaluate(left));
+                    }
+                },
+                Work::BinaryRight { operator, right } => {
+                    // Arithmetic operators reject a bad left operand before the right
+                    // operand is evaluated.
+                    if let TokenType::Minus | TokenType::Slash | TokenType::Star =
+                        operator.token_type
                     {
-                        return Ok(Some(Rc::new(l + r)));
+                        Expr::number_operand(
+                            values.last().unwrap(),
+                            operator,
+                            "Operands must be numbers.",
+                        )?;
                     }
-
-                    if let (Some(l), Some(r)) = (
-                        left.downcast_ref::<String>(),
-                        right.downcast_ref::<String>(),
-                    ) {
-                        return Ok(Some(Rc::new(l.clone() + r)));
+                    work.push(Work::Binary(operator));
+                    work.push(Work::Evaluate(right));
+                }
+                Work::Binary(operator) => {
+                    let right = values.pop().unwrap();
+                    let left = values.pop().unwrap();
+                    values.push(Expr::binary(left, operator, right, interpreter)?);
+                }
+                Work::Unary(operator) => {
+                    let right = values.pop().unwrap();
+                    values.push(Expr::unary(operator, right)?);
+                }
+                Work::LogicalRight { operator, right } => {
+                    let left = values.last().unwrap().is_truthy();
+                    let decided = match operator.token_type {
+                        TokenType::Or => left,
+                        _ => !left,
+                    };
+                    if !decided {
+                        values.pop();
+                        work.push(Work::Evaluate(right));
                     }
-
-                    Err(err)
                 }
-                TokenType::EqualEqual => {
-                    let left = left.interpret()?;
-                    let right = right.interpret()?;
-                    Ok(Some(Rc::new(Expr::equals(left, right))))
+                Work::Call { paren, count } => {
+                    let arguments = values.split_off(values.len() - count);
+                    let callee = values.pop().unwrap();
+                    values.push(interpreter.call(callee, arguments, paren)?);
                 }
-                TokenType::BangEqual => {
-                    let left = left.interpret()?;
-                    let right = right.interpret()?;
-                    Ok(Some(Rc::new(!Expr::equals(left, right))))
+                Work::Assign { name, id } => {
+                    interpreter.assign_variable(name, id, values.last().unwrap().clone())?
                 }
-                _ => Err(RuntimeError {
-                    message: "Invalid binary operator.".to_string(),
-                    token: operator,
-                }),
-            },
+                Work::Get(name) => {
+                    let object = values.pop().unwrap();
+                    values.push(Expr::get_property(object, name)?);
+                }
+                Work::Index(bracket) => {
+                    let index = values.pop().unwrap();
+                    let object = values.pop().unwrap();
+                    values.push(Expr::index(object, bracket, index)?);
+                }
+                Work::Set(name) => {
+                    let value = values.pop().unwrap();
+                    let object = values.pop().unwrap();
+                    values.push(Expr::set_property(object, name, value)?);
+                }
+            }
         }
+
+        Ok(values.pop().unwrap())
     }
 
-    fn is_truthy(obj: Option<Rc<dyn Any>>) -> bool {
-        match obj {
-            Some(v) => match v.downcast_ref::<bool>() {
-                Some(val) => *val,
-                None => true,
-            },
-            None => false,
+    // Moves this node's subexpressions into `into`, leaving nil literals behind.
+    fn detach_children(&mut self, into: &mut Vec<Expr>) {
+        let mut detach = |child: &mut Box<Expr>| {
+            into.push(mem::replace(
+                child.as_mut(),
+                Expr::Literal { value: Value::Nil },
+            ));
+        };
+        match self {
+            Expr::Assign { value, .. } => detach(value),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                detach(left);
+                detach(right);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                detach(callee);
+                into.append(arguments);
+            }
+            Expr::Get { object, .. } => detach(object),
+            Expr::Grouping { expression } => detach(expression),
+            Expr::Index { object, index, .. } => {
+                detach(object);
+                detach(index);
+            }
+            Expr::Set { object, value, .. } => {
+                detach(object);
+                detach(value);
+            }
+            Expr::Unary { right, .. } => detach(right),
+            Expr::Literal { .. } | Expr::This { .. } | Expr::Variable { .. } => {}
         }
     }
 
-    fn equals(a: Option<Rc<dyn Any>>, b: Option<Rc<dyn Any>>) -> bool {
-        if a.is_none() && b.is_none() {
-            return true;
+    pub(crate) fn get_property(object: Value, name: &Token) -> Result<Value, RuntimeError> {
+        match object {
+            Value::Instance(instance) => instance.get(name),
+            _ => Err(RuntimeError::new(
+                name.clone(),
+                "Only instances have properties.",
+            )),
         }
-        if a.is_none() {
-            return false;
+    }
+
+    /// The element of an array at a whole-number index.
+    pub(crate) fn index(
+        object: Value,
+        bracket: &Token,
+        index: Value,
+    ) -> Result<Value, RuntimeError> {
+        let Value::Array(elements) = object else {
+            return Err(RuntimeError::new(
+                bracket.clone(),
+                "Only arrays can be indexed.",
+            ));
+        };
+        let Ok(index) = i64::try_from(index) else {
+            return Err(RuntimeError::new(
+                bracket.clone(),
+                "Array index must be an integer.",
+            ));
+        };
+        match usize::try_from(index).ok().and_then(|i| elements.get(i)) {
+            Some(element) => Ok(element.clone()),
+            None => Err(RuntimeError::new(
+                bracket.clone(),
+                &format!(
+                    "Array index {} is out of range for length {}.",
+                    index,
+                    elements.len()
+                ),
+            )),
         }
+    }
 
-        let a = a.unwrap();
-        let b = b.unwrap();
+    /// Sets a field, returning the value as the result of the assignment.
+    pub(crate) fn set_property(
+        object: Value,
+        name: &Token,
+        value: Value,
+    ) -> Result<Value, RuntimeError> {
+        match object {
+            Value::Instance(instance) => {
+                instance.set(name, value.clone());
+                Ok(value)
+            }
+            _ => Err(RuntimeError::new(
+                name.clone(),
+                "Only instances have fields.",
+            )),
+        }
+    }
 
-        if let (Some(a), Some(b)) = (a.downcast_ref::<f64>(), b.downcast_ref::<f64>()) {
-            return a == b;
+    pub(crate) fn unary(operator: &Token, right: Value) -> Result<Value, RuntimeError> {
+        match operator.token_type {
+            TokenType::Bang => Ok(Value::Bool(!right.is_truthy())),
+            TokenType::Minus => match right {
+                Value::Int(i) => i
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| RuntimeError::new(operator.clone(), "Integer overflow.")),
+                Value::Number(n) => Ok(Value::Number(-n)),
+                _ => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operand must be a number.",
+                )),
+            },
+            _ => Err(RuntimeError::new(
+                operator.clone(),
+                "Invalid unary operator.",
+            )),
         }
-        if let (Some(a), Some(b)) = (a.downcast_ref::<bool>(), b.downcast_ref::<bool>()) {
-            return a == b;
+    }
+
+    pub(crate) fn binary(
+        left: Value,
+        operator: &Token,
+        right: Value,
+        interpreter: &mut Interpreter,
+    ) -> Result<Value, RuntimeError> {
+        match operator.token_type {
+            TokenType::Minus | TokenType::Slash | TokenType::Star => {
+                if !left.is_number() || !right.is_number() {
+                    return Err(RuntimeError::new(
+                        operator.clone(),
+                        "Operands must be numbers.",
+                    ));
+                }
+                Expr::arithmetic(left, operator, right)
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                // Strings are ordered lexicographically by Unicode scalar value,
+                // so "A" < "a" and "Z" < "a".
+                let ordering = match (&left, &right) {
+                    (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+                    (l, r) if l.is_number() && r.is_number() => {
+                        l.as_f64().unwrap().partial_cmp(&r.as_f64().unwrap())
+                    }
+                    (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                    _ => return Err(Expr::comparison_error(&left, operator, &right, interpreter)),
+                };
+
+                // NaN compares false against everything.
+                let result = match ordering {
+                    Some(ordering) => match operator.token_type {
+                        TokenType::Greater => ordering.is_gt(),
+                        TokenType::GreaterEqual => ordering.is_ge(),
+                        TokenType::Less => ordering.is_lt(),
+                        TokenType::LessEqual => ordering.is_le(),
+                        _ => unreachable!(),
+                    },
+                    None => false,
+                };
+                Ok(Value::Bool(result))
+            }
+            TokenType::Plus => match (left, right) {
+                (left, right) if left.is_number() && right.is_number() => {
+                    Expr::arithmetic(left, operator, right)
+                }
+                // If either side is a string, the other side is stringified like `print` would.
+                (left @ Value::String(_), right) | (left, right @ Value::String(_)) => {
+                    Ok(Value::String(stringify(left) + &stringify(right)))
+                }
+                _ => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be two numbers or two strings.",
+                )),
+            },
+            TokenType::EqualEqual | TokenType::BangEqual => {
+                // Comparing against nil is the idiomatic nil check, so it never warns.
+                let (l, r) = (left.type_name(), right.type_name());
+                if interpreter.is_strict() && l != r && left != Value::Nil && right != Value::Nil {
+                    interpreter.warn(
+                        operator,
+                        &format!(
+                            "Comparing values of different types ({} and {}) with '{}'.",
+                            l, r, operator.lexeme
+                        ),
+                    );
+                }
+
+                let equal = left == right;
+                match operator.token_type {
+                    TokenType::EqualEqual => Ok(Value::Bool(equal)),
+                    _ => Ok(Value::Bool(!equal)),
+                }
+            }
+            _ => Err(RuntimeError::new(
+                operator.clone(),
+                "Invalid binary operator.",
+            )),
         }
-        if let (Some(a), Some(b)) = (a.downcast_ref::<String>(), b.downcast_ref::<String>()) {
-            return a == b;
+    }
+
+    fn comparison_error(
+        left: &Value,
+        operator: &Token,
+        right: &Value,
+        interpreter: &mut Interpreter,
+    ) -> RuntimeError {
+        let message = if interpreter.is_strict() {
+            format!(
+                "Operands to '{}' must be two numbers or two strings, got {} and {}.",
+                operator.lexeme,
+                left.type_name(),
+                right.type_name()
+            )
+        } else {
+            "Operands must be two numbers or two strings.".to_string()
+        };
+
+        RuntimeError::new(operator.clone(), &message)
+    }
+
+    pub(crate) fn number_operand(
+        val: &Value,
+        token: &Token,
+        message: &str,
+    ) -> Result<f64, RuntimeError> {
+        val.as_f64()
+            .ok_or_else(|| RuntimeError::new(token.clone(), message))
+    }
+
+    /// Applies `+`, `-`, `*` or `/` to two numeric operands. Two ints stay an int, with
+    /// overflow reported as an error rather than wrapping, except for `/`, which always
+    /// produces a float (`7 / 2` is `3.5`). Mixing an int with a float promotes to float.
+    fn arithmetic(left: Value, operator: &Token, right: Value) -> Result<Value, RuntimeError> {
+        if let (Value::Int(l), Value::Int(r)) = (&left, &right) {
+            let result = match operator.token_type {
+                TokenType::Plus => l.checked_add(*r),
+                TokenType::Minus => l.checked_sub(*r),
+                TokenType::Star => l.checked_mul(*r),
+                TokenType::Slash => return Ok(Value::Number(*l as f64 / *r as f64)),
+                _ => unreachable!(),
+            };
+            return result
+                .map(Value::Int)
+                .ok_or_else(|| RuntimeError::new(operator.clone(), "Integer overflow."));
         }
 
-        false
-    }
-
-    fn try_convert<T>(self, token: &Token, message: &str) -> Result<T, RuntimeError>
-    where
-        T: 'static + Copy,
-    {
-        let val = self.interpret()?;
-        match val {
-            Some(v) => match v.downcast_ref::<T>() {
-                Some(val) => Ok(*val),
-                None => Err(RuntimeError {
-                    message: message.to_string(),
-                    token: token.clone(),
-                }),
-            },
-            None => Err(RuntimeError {
-                message: message.to_string(),
-                token: token.clone(),
-            }),
+        let (l, r) = (left.as_f64().unwrap(), right.as_f64().unwrap());
+        match operator.token_type {
+            TokenType::Plus => Ok(Value::Number(l + r)),
+            TokenType::Minus => Ok(Value::Number(l - r)),
+            TokenType::Star => Ok(Value::Number(l * r)),
+            TokenType::Slash => Ok(Value::Number(l / r)),
+            _ => unreachable!(),
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallFrame {
+    pub name: String,
+    pub line: usize,
+}
+
 #[derive(Clone)]
 pub struct RuntimeError {
-    token: Token,
+    // Errors raised from native code have no token to point at.
+    token: Option<Box<Token>>,
     message: String,
+    frames: Option<Vec<CallFrame>>,
+}
+
+impl RuntimeError {
+    pub fn new(token: Token, message: &str) -> Self {
+        Self {
+            token: Some(Box::new(token)),
+            message: message.to_string(),
+            frames: None,
+        }
+    }
+
+    pub fn native(message: &str) -> Self {
+        Self {
+            token: None,
+            message: message.to_string(),
+            frames: None,
+        }
+    }
+
+    pub fn with_frames(mut self, frames: Vec<CallFrame>) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.token.as_ref().map(|t| t.line)
+    }
+
+    pub fn lexeme(&self) -> Option<&str> {
+        self.token.as_ref().map(|t| t.lexeme.as_str())
+    }
+
+    pub fn frames(&self) -> Option<&[CallFrame]> {
+        self.frames.as_deref()
+    }
+}
+
+impl RuntimeError {
+    /// The error as reported to the user, with ANSI colors if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let location = match &self.token {
+            Some(token) => format!("{}:{}", token.source, token.line),
+            None => "[native]".to_string(),
+        };
+        let mut text = style::diagnostic(&location, "RuntimeError", &self.message, color);
+
+        if let Some(frames) = &self.frames {
+            for frame in frames {
+                text.push_str(&format!("\nin {} (line {})", frame.name, frame.line));
+            }
+        }
+        text
+    }
 }
 
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} \n[line {}]", self.message, self.token.line)
+        write!(f, "{}", self.render(false))
     }
 }
 
-// impl Display for Expr {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         let text = match self {
-//             Expr::Binary {
-//                 left,
-//                 operator,
-//                 right,
-//             } => parenthesize!(operator.lexeme, left, right),
-//             Expr::Grouping { expression } => parenthesize!("group", expression),
-//             Expr::Literal { value } => match value {
-//                 Some(v) => v.to_string(),
-//                 None => String::from("null"),
-//             },
-//             Expr::Unary { operator, right } => parenthesize!(operator.lexeme, right),
-//         };
-//         write!(f, "{text}")
-//     }
-// }
+// The parenthesized form from the book, used by `--ast`.
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        printer::write(Piece::Expr(self), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn evaluate(source: &str) -> Result<Value, RuntimeError> {
+        let mut scanner = Scanner::new(source.to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        Parser::new(tokens)
+            .parse_expression()
+            .unwrap()
+            .interpret(&mut Interpreter::default())
+    }
+
+    fn print(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        Parser::new(tokens).parse_expression().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_display_parenthesizes() {
+        assert_eq!(print("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(print("-(1.5)"), "(- (group 1.5))");
+        assert_eq!(print("\"a\" != nil"), "(!= \"a\" nil)");
+        assert_eq!(print("a.b.c"), "(. (. a b) c)");
+        assert_eq!(print("a.b().c = 1"), "(= (. (call (. a b)) c) 1)");
+        assert_eq!(print("f()[i + 1]"), "(index (call f) (+ i 1))");
+    }
+
+    fn evaluate_string(source: &str) -> String {
+        String::try_from(evaluate(source).ok().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_concat_string_number() {
+        assert_eq!(evaluate_string("\"count: \" + 3"), "count: 3");
+        assert_eq!(evaluate_string("\"half: \" + 0.5"), "half: 0.5");
+    }
+
+    #[test]
+    fn test_concat_number_string() {
+        assert_eq!(evaluate_string("3 + \" apples\""), "3 apples");
+    }
+
+    #[test]
+    fn test_concat_string_nil() {
+        assert_eq!(evaluate_string("\"value: \" + nil"), "value: nil");
+    }
+
+    #[test]
+    fn test_concat_bool_string() {
+        assert_eq!(evaluate_string("true + \"!\""), "true!");
+    }
+
+    #[test]
+    fn test_add_numbers() {
+        assert_eq!(evaluate("1 + 2").ok(), Some(Value::Int(3)));
+    }
+
+    fn evaluate_bool(source: &str) -> bool {
+        bool::try_from(evaluate(source).ok().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_compare_strings() {
+        assert!(evaluate_bool("\"apple\" < \"banana\""));
+        assert!(evaluate_bool("\"apple\" <= \"apple\""));
+        assert!(evaluate_bool("\"banana\" > \"apple\""));
+        assert!(evaluate_bool("\"banana\" >= \"banana\""));
+        assert!(!evaluate_bool("\"banana\" < \"apple\""));
+        assert!(evaluate_bool("\"app\" < \"apple\""));
+    }
+
+    #[test]
+    fn test_compare_strings_code_point_order() {
+        // Uppercase letters come before lowercase ones in code point order.
+        assert!(evaluate_bool("\"A\" < \"a\""));
+        assert!(evaluate_bool("\"Z\" < \"a\""));
+    }
+
+    #[test]
+    fn test_compare_mixed_error() {
+        let err = evaluate("\"1\" < 2").err().unwrap();
+        assert_eq!(
+            err.message(),
+            "Operands must be two numbers or two strings."
+        );
+        let err = evaluate("nil >= 2").err().unwrap();
+        assert_eq!(
+            err.message(),
+            "Operands must be two numbers or two strings."
+        );
+    }
+
+    #[test]
+    fn test_evaluate_long_sum() {
+        let source = vec!["1"; 100_000].join(" + ");
+        assert_eq!(evaluate(&source).ok(), Some(Value::Int(100_000)));
+    }
+
+    #[test]
+    fn test_display_long_sum() {
+        let source = vec!["1"; 100_000].join(" + ");
+        let printed = print(&source);
+        assert!(printed.starts_with("(+ (+ (+ "));
+        assert!(printed.ends_with(" 1) 1) 1)"));
+        assert_eq!(printed.matches('+').count(), 99_999);
+    }
+
+    #[test]
+    fn test_evaluation_order_preserved() {
+        // The left operand's type error wins over an error in the right operand.
+        let err = evaluate("\"a\" - (-\"b\")").err().unwrap();
+        assert_eq!(err.message(), "Operands must be numbers.");
+        let err = evaluate("1 - (-\"b\")").err().unwrap();
+        assert_eq!(err.message(), "Operand must be a number.");
+        assert_eq!(evaluate("(8 - 4) - 2").ok(), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_equals_nil() {
+        assert!(evaluate_bool("nil == nil"));
+        assert!(!evaluate_bool("3 == nil"));
+        assert!(!evaluate_bool("nil == 3"));
+        assert!(evaluate_bool("3 != nil"));
+    }
+
+    #[test]
+    fn test_strict_comparison_error_names_types() {
+        let mut scanner = Scanner::new("nil < 1".to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        let err = Parser::new(tokens)
+            .parse_expression()
+            .unwrap()
+            .interpret(&mut Interpreter::builder().strict(true).build())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.message(),
+            "Operands to '<' must be two numbers or two strings, got nil and number."
+        );
+    }
+
+    #[test]
+    fn test_runtime_error_accessors() {
+        let err = evaluate("1 + true").err().unwrap();
+        assert_eq!(
+            err.message(),
+            "Operands must be two numbers or two strings."
+        );
+        assert_eq!(err.line(), Some(1));
+        assert_eq!(err.lexeme(), Some("+"));
+        assert_eq!(err.frames(), None);
+        assert_eq!(
+            err.to_string(),
+            "<test>:1: RuntimeError: Operands must be two numbers or two strings."
+        );
+    }
+
+    #[test]
+    fn test_native_runtime_error() {
+        let err = RuntimeError::native("Expected a string argument.");
+        assert_eq!(err.line(), None);
+        assert_eq!(err.lexeme(), None);
+        assert_eq!(
+            err.to_string(),
+            "[native]: RuntimeError: Expected a string argument."
+        );
+    }
+
+    #[test]
+    fn test_runtime_error_with_frames() {
+        let err = RuntimeError::native("Boom.").with_frames(vec![
+            CallFrame {
+                name: "inner".to_string(),
+                line: 2,
+            },
+            CallFrame {
+                name: "outer".to_string(),
+                line: 5,
+            },
+        ]);
+        assert_eq!(err.frames().unwrap().len(), 2);
+        assert_eq!(
+            err.to_string(),
+            "[native]: RuntimeError: Boom.\nin inner (line 2)\nin outer (line 5)"
+        );
+    }
+
+    #[test]
+    fn test_int_arithmetic() {
+        assert_eq!(evaluate("2 + 3").ok(), Some(Value::Int(5)));
+        assert_eq!(evaluate("2 - 3").ok(), Some(Value::Int(-1)));
+        assert_eq!(evaluate("4 * 5").ok(), Some(Value::Int(20)));
+        assert_eq!(evaluate("-(4)").ok(), Some(Value::Int(-4)));
+    }
+
+    #[test]
+    fn test_int_division_is_float() {
+        assert_eq!(evaluate("7 / 2").ok(), Some(Value::Number(3.5)));
+        assert_eq!(evaluate("6 / 2").ok(), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_mixed_arithmetic_promotes() {
+        assert_eq!(evaluate("1 + 0.5").ok(), Some(Value::Number(1.5)));
+        assert_eq!(evaluate("0.5 * 4").ok(), Some(Value::Number(2.0)));
+        assert_eq!(evaluate("3 - 1.0").ok(), Some(Value::Number(2.0)));
+        assert_eq!(evaluate("1.5 + 1.5").ok(), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_int_overflow() {
+        let err = evaluate("9223372036854775807 + 1").err().unwrap();
+        assert_eq!(err.message(), "Integer overflow.");
+        let err = evaluate("9223372036854775807 * 2").err().unwrap();
+        assert_eq!(err.message(), "Integer overflow.");
+        let err = evaluate("-9223372036854775807 - 2").err().unwrap();
+        assert_eq!(err.message(), "Integer overflow.");
+    }
+
+    #[test]
+    fn test_int_precision() {
+        // Past 2^53 a float would lose the final digit.
+        assert_eq!(
+            evaluate("9007199254740993 + 0").ok(),
+            Some(Value::Int(9007199254740993))
+        );
+    }
+
+    #[test]
+    fn test_numeric_equality_across_kinds() {
+        assert!(evaluate_bool("1 == 1.0"));
+        assert!(evaluate_bool("1.0 == 1"));
+        assert!(!evaluate_bool("1 == 1.5"));
+        assert!(evaluate_bool("1 != 1.5"));
+        assert!(evaluate_bool("2 == 2"));
+    }
+
+    #[test]
+    fn test_numeric_comparison_across_kinds() {
+        assert!(evaluate_bool("1 < 1.5"));
+        assert!(evaluate_bool("2.5 > 2"));
+        assert!(evaluate_bool("2 >= 2.0"));
+        assert!(evaluate_bool("3 <= 4"));
+    }
+
+    #[test]
+    fn test_logical_returns_operand() {
+        assert_eq!(evaluate("nil or \"yes\"").ok(), Some(Value::from("yes")));
+        assert_eq!(evaluate("0 or 1").ok(), Some(Value::Int(0)));
+        assert_eq!(evaluate("nil and 1").ok(), Some(Value::Nil));
+        assert_eq!(evaluate("1 and 2").ok(), Some(Value::Int(2)));
+        // `or` binds more loosely than `and`, which binds more loosely than `==`.
+        assert_eq!(print("a or b and c == d"), "(or a (and b (== c d)))");
+        assert_eq!(
+            evaluate("true or false and false").ok(),
+            Some(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_logical_short_circuits() {
+        assert_eq!(evaluate("true or -nil").ok(), Some(Value::Bool(true)));
+        assert_eq!(evaluate("false and -nil").ok(), Some(Value::Bool(false)));
+        let err = evaluate("false or -nil").err().unwrap();
+        assert_eq!(err.message(), "Operand must be a number.");
+    }
+
+    #[test]
+    fn test_add_number_bool_error() {
+        let err = evaluate("1 + true").err().unwrap();
+        assert_eq!(
+            err.message(),
+            "Operands must be two numbers or two strings."
+        );
+    }
+}
@@ -1,21 +1,48 @@
-use crate::{
-    lox,
-    scanner::{Token, TokenType},
-};
-use std::{fmt::Display, mem::discriminant, rc::Rc};
+use crate::scanner::{ScanError, Token, TokenType};
+use crate::value::Value;
+use std::{fmt::Display, mem, mem::discriminant, sync::Arc, sync::OnceLock};
+
+// The most arguments a call can pass, and so the most parameters a function can have.
+const MAX_ARGUMENTS: usize = 255;
+
+// How deeply code can nest, counting both statements and expressions. The parser
+// recurses for each level, so without a limit generated code could overflow the stack.
+// Code this deep still fits the interpreter's STACK_SIZE, even unoptimized.
+const MAX_NESTING: usize = 3000;
 
 mod expr;
-pub use expr::Expr;
-pub use expr::RuntimeError;
+mod printer;
+mod stmt;
+pub use expr::{CallFrame, RuntimeError};
+pub use expr::{Expr, ExprId};
+pub use stmt::{Function, Stmt};
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    // Levels of `nested` currently being parsed.
+    depth: usize,
+    // Set once nesting is too deep. Resynchronizing inside the nested code would only
+    // hit the limit again at every level, so parsing stops instead.
+    too_deep: bool,
 }
 
 #[derive(Debug, Clone)]
-struct ParseError {
-    message: String,
+pub struct ParseError {
+    pub source: Arc<str>,
+    pub line: usize,
+    pub message: String,
+}
+
+// The front end reports scan errors alongside parse and resolver errors.
+impl From<ScanError> for ParseError {
+    fn from(e: ScanError) -> Self {
+        ParseError {
+            source: e.source,
+            line: e.line,
+            message: e.message,
+        }
+    }
 }
 
 impl Display for ParseError {
@@ -26,11 +53,288 @@ impl Display for ParseError {
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            depth: 0,
+            too_deep: false,
+        }
+    }
+
+    /// Parses a whole program. After an error the parser skips to the next statement,
+    /// so every syntax error is returned rather than just the first.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    errors.push(e);
+                    if self.too_deep {
+                        break;
+                    }
+                    self.synchronize();
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
     }
 
-    pub fn parse(&mut self) -> Option<Expr> {
-        self.expression().ok()
+    /// Parses the tokens as one expression with nothing after it, as typed at the REPL.
+    pub fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(self.error(self.peek().unwrap(), "Expect end of expression."));
+        }
+        Ok(expr)
+    }
+
+    fn declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.is_match(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.is_match(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(Arc::new(self.function("function")?)));
+        }
+        if self.is_match(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+
+        self.statement()
+    }
+
+    // Methods are written like functions, without the `fun` keyword.
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect class name.")?
+            .clone();
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(Arc::new(self.function("method")?));
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class { name, methods })
+    }
+
+    // `kind` names what's being declared in error messages.
+    fn function(&mut self, kind: &str) -> Result<Function, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, &format!("Expect {} name.", kind))?
+            .clone();
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(self.error(
+                        self.peek().unwrap(),
+                        &format!("Can't have more than {} parameters.", MAX_ARGUMENTS),
+                    ));
+                }
+                params.push(
+                    self.consume(TokenType::Identifier, "Expect parameter name.")?
+                        .clone(),
+                );
+                if !self.is_match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body = self.block()?;
+        Ok(Function {
+            name,
+            params,
+            body,
+            chunk: OnceLock::new(),
+        })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect variable name.")?
+            .clone();
+
+        let initializer = if self.is_match(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var { name, initializer })
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.is_match(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.is_match(&[TokenType::If]) {
+            return self.if_statement();
+        }
+        if self.is_match(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+        if self.is_match(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.is_match(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.is_match(&[TokenType::LeftBrace]) {
+            return Ok(Stmt::Block {
+                statements: self.block()?,
+            });
+        }
+
+        self.expression_statement()
+    }
+
+    // The statements up to the closing brace; the opening one has been consumed.
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.nested("Statement", Self::declaration)?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    // There's no for loop at runtime: it's rewritten into a while loop, with the
+    // increment after the body and the initializer in a block around both.
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        let initializer = if self.is_match(&[TokenType::Semicolon]) {
+            None
+        } else if self.is_match(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(TokenType::Semicolon) {
+            Expr::Literal {
+                value: Value::Bool(true),
+            }
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.nested("Statement", Self::statement)?;
+        if let Some(increment) = increment {
+            body = Stmt::Block {
+                statements: vec![
+                    body,
+                    Stmt::Expression {
+                        expression: increment,
+                    },
+                ],
+            };
+        }
+        body = Stmt::While {
+            keyword,
+            condition,
+            body: Box::new(body),
+        };
+        if let Some(initializer) = initializer {
+            body = Stmt::Block {
+                statements: vec![initializer, body],
+            };
+        }
+
+        Ok(body)
+    }
+
+    // An `else` belongs to the nearest `if`, since the then branch claims it first.
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.nested("Statement", Self::statement)?);
+        let else_branch = if self.is_match(&[TokenType::Else]) {
+            Some(Box::new(self.nested("Statement", Self::statement)?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let value = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.nested("Statement", Self::statement)?);
+
+        Ok(Stmt::While {
+            keyword,
+            condition,
+            body,
+        })
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print { expression })
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression { expression })
     }
 
     fn binary<F>(&mut self, match_expr: F, token_types: &[TokenType]) -> Result<Expr, ParseError>
@@ -52,7 +356,70 @@ impl Parser {
     }
 
     fn expression(&mut self) -> Result<Expr, ParseError> {
-        self.equality()
+        self.nested("Expression", Self::assignment)
+    }
+
+    // Assignment is right-associative, so the value is parsed by recursing. The target
+    // is parsed as an ordinary expression first and only then checked to be a variable
+    // or a property.
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.or()?;
+
+        if self.is_match(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let value = self.nested("Expression", Self::assignment)?;
+
+            match &mut expr {
+                Expr::Variable { name, .. } => {
+                    return Ok(Expr::Assign {
+                        id: ExprId::next(),
+                        name: name.clone(),
+                        value: Box::new(value),
+                    })
+                }
+                // `Expr` implements Drop, so the object can't be moved out; it's
+                // swapped for a placeholder instead.
+                Expr::Get { object, name } => {
+                    let object =
+                        mem::replace(object, Box::new(Expr::Literal { value: Value::Nil }));
+                    return Ok(Expr::Set {
+                        object,
+                        name: name.clone(),
+                        value: Box::new(value),
+                    });
+                }
+                _ => return Err(self.error(&equals, "Invalid assignment target.")),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        self.logical(Self::and, TokenType::Or)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        self.logical(Self::equality, TokenType::And)
+    }
+
+    // Like `binary`, but for the short-circuiting operators.
+    fn logical<F>(&mut self, match_expr: F, token_type: TokenType) -> Result<Expr, ParseError>
+    where
+        F: Fn(&mut Self) -> Result<Expr, ParseError>,
+    {
+        let mut expr = match_expr(self)?;
+
+        while self.is_match(&[token_type]) {
+            let operator = self.previous().clone();
+            let right = match_expr(self)?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, ParseError> {
@@ -85,30 +452,98 @@ impl Parser {
     fn unary(&mut self) -> Result<Expr, ParseError> {
         if self.is_match(&[TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested("Expression", Self::unary)?;
             return Ok(Expr::Unary {
                 operator,
                 right: Box::new(right),
             });
         }
 
-        self.primary()
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.is_match(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.is_match(&[TokenType::Dot]) {
+                let name = self
+                    .consume(TokenType::Identifier, "Expect property name after '.'.")?
+                    .clone();
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else if self.is_match(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self
+                    .consume(TokenType::RightBracket, "Expect ']' after index.")?
+                    .clone();
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    return Err(self.error(
+                        self.peek().unwrap(),
+                        &format!("Can't have more than {} arguments.", MAX_ARGUMENTS),
+                    ));
+                }
+                arguments.push(self.expression()?);
+                if !self.is_match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after arguments.")?
+            .clone();
+        Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        })
     }
 
     fn primary(&mut self) -> Result<Expr, ParseError> {
         if self.is_match(&[TokenType::False]) {
             Ok(Expr::Literal {
-                value: Some(Rc::new(false)),
+                value: Value::Bool(false),
             })
         } else if self.is_match(&[TokenType::True]) {
             Ok(Expr::Literal {
-                value: Some(Rc::new(true)),
+                value: Value::Bool(true),
             })
         } else if self.is_match(&[TokenType::Nil]) {
-            Ok(Expr::Literal { value: None })
+            Ok(Expr::Literal { value: Value::Nil })
         } else if self.is_match(&[TokenType::Number, TokenType::String]) {
             Ok(Expr::Literal {
-                value: self.previous().literal.clone(),
+                value: self.previous().literal.clone().unwrap(),
+            })
+        } else if self.is_match(&[TokenType::This]) {
+            Ok(Expr::This {
+                id: ExprId::next(),
+                keyword: self.previous().clone(),
+            })
+        } else if self.is_match(&[TokenType::Identifier]) {
+            Ok(Expr::Variable {
+                id: ExprId::next(),
+                name: self.previous().clone(),
             })
         } else if self.is_match(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
@@ -122,6 +557,23 @@ impl Parser {
         }
     }
 
+    // Runs `parse` one level deeper, failing once `what` nests too deeply. The depth
+    // is restored whether or not `parse` succeeds.
+    fn nested<T, F>(&mut self, what: &str, parse: F) -> Result<T, ParseError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, ParseError>,
+    {
+        if self.depth >= MAX_NESTING {
+            self.too_deep = true;
+            let message = format!("{} nesting too deep.", what);
+            return Err(self.error(self.peek().unwrap(), &message));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, ParseError> {
         if !self.check(token_type) {
             return Err(self.error(self.peek().unwrap(), message));
@@ -131,8 +583,9 @@ impl Parser {
     }
 
     fn error(&self, token: &Token, message: &str) -> ParseError {
-        lox::error(token.line, message);
         ParseError {
+            source: token.source.clone(),
+            line: token.line,
             message: message.to_string(),
         }
     }
@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+use std::fmt::{self, Formatter};
+
+use super::{Expr, Function, Stmt};
+use crate::interpreter::stringify;
+use crate::value::Value;
+
+// Pending output for the `--ast` printer. Like `Expr::interpret`, printing walks the
+// tree with an explicit stack so that very deep trees can't overflow the Rust stack.
+pub(super) enum Piece<'a> {
+    Expr(&'a Expr),
+    Stmt(&'a Stmt),
+    Function(&'a Function),
+    Text(Cow<'a, str>),
+    // Printed in parentheses, separated by spaces.
+    List(Vec<Piece<'a>>),
+}
+
+impl<'a> From<&'a str> for Piece<'a> {
+    fn from(text: &'a str) -> Self {
+        Piece::Text(Cow::Borrowed(text))
+    }
+}
+
+// The parenthesized form from the book: `name` and then each item, separated by spaces.
+fn parenthesize<'a>(name: &'a str, items: impl IntoIterator<Item = Piece<'a>>) -> Piece<'a> {
+    let mut pieces = vec![Piece::from(name)];
+    pieces.extend(items);
+    Piece::List(pieces)
+}
+
+pub(super) fn write(root: Piece, f: &mut Formatter<'_>) -> fmt::Result {
+    let mut pending = vec![root];
+
+    while let Some(piece) = pending.pop() {
+        match piece {
+            Piece::Text(text) => f.write_str(&text)?,
+            Piece::List(items) => {
+                // Pushed in reverse, so the opening parenthesis comes off first.
+                pending.push(Piece::from(")"));
+                for (i, item) in items.into_iter().enumerate().rev() {
+                    pending.push(item);
+                    if i > 0 {
+                        pending.push(Piece::from(" "));
+                    }
+                }
+                pending.push(Piece::from("("));
+            }
+            Piece::Expr(expr) => pending.push(expand_expr(expr)),
+            Piece::Stmt(stmt) => pending.push(expand_stmt(stmt)),
+            Piece::Function(function) => pending.push(expand_function(function)),
+        }
+    }
+    Ok(())
+}
+
+fn expand_expr(expr: &Expr) -> Piece<'_> {
+    match expr {
+        Expr::Assign { name, value, .. } => {
+            parenthesize("=", [Piece::from(name.lexeme.as_str()), Piece::Expr(value)])
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        }
+        | Expr::Logical {
+            left,
+            operator,
+            right,
+        } => parenthesize(&operator.lexeme, [Piece::Expr(left), Piece::Expr(right)]),
+        Expr::Call {
+            callee, arguments, ..
+        } => parenthesize(
+            "call",
+            std::iter::once(Piece::Expr(callee)).chain(arguments.iter().map(Piece::Expr)),
+        ),
+        Expr::Get { object, name } => parenthesize(
+            ".",
+            [Piece::Expr(object), Piece::from(name.lexeme.as_str())],
+        ),
+        Expr::Grouping { expression } => parenthesize("group", [Piece::Expr(expression)]),
+        Expr::Index { object, index, .. } => {
+            parenthesize("index", [Piece::Expr(object), Piece::Expr(index)])
+        }
+        Expr::Literal { value } => Piece::Text(Cow::Owned(match value {
+            Value::String(s) => format!("{:?}", s),
+            v => stringify(v.clone()),
+        })),
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => {
+            let target = parenthesize(
+                ".",
+                [Piece::Expr(object), Piece::from(name.lexeme.as_str())],
+            );
+            parenthesize("=", [target, Piece::Expr(value)])
+        }
+        Expr::This { .. } => Piece::from("this"),
+        Expr::Unary { operator, right } => parenthesize(&operator.lexeme, [Piece::Expr(right)]),
+        Expr::Variable { name, .. } => Piece::from(name.lexeme.as_str()),
+    }
+}
+
+// Statements print in the same parenthesized style as expressions.
+fn expand_stmt(stmt: &Stmt) -> Piece<'_> {
+    match stmt {
+        Stmt::Block { statements } => parenthesize("block", statements.iter().map(Piece::Stmt)),
+        Stmt::Class { name, methods } => parenthesize(
+            "class",
+            std::iter::once(Piece::from(name.lexeme.as_str()))
+                .chain(methods.iter().map(|method| Piece::Function(method))),
+        ),
+        Stmt::Expression { expression } => parenthesize(";", [Piece::Expr(expression)]),
+        Stmt::Function(function) => Piece::Function(function),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => parenthesize(
+            "if",
+            [Piece::Expr(condition), Piece::Stmt(then_branch)]
+                .into_iter()
+                .chain(else_branch.as_deref().map(Piece::Stmt)),
+        ),
+        Stmt::Print { expression } => parenthesize("print", [Piece::Expr(expression)]),
+        Stmt::Return { value, .. } => parenthesize("return", value.as_ref().map(Piece::Expr)),
+        Stmt::Var { name, initializer } => parenthesize(
+            "var",
+            std::iter::once(Piece::from(name.lexeme.as_str()))
+                .chain(initializer.as_ref().map(Piece::Expr)),
+        ),
+        Stmt::While {
+            condition, body, ..
+        } => parenthesize("while", [Piece::Expr(condition), Piece::Stmt(body)]),
+    }
+}
+
+fn expand_function(function: &Function) -> Piece<'_> {
+    let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+    let params = Piece::Text(Cow::Owned(format!("({})", params.join(" "))));
+    parenthesize(
+        "fun",
+        [Piece::from(function.name.lexeme.as_str()), params]
+            .into_iter()
+            .chain(function.body.iter().map(Piece::Stmt)),
+    )
+}
@@ -0,0 +1,73 @@
+use std::fmt::Display;
+use std::sync::{Arc, OnceLock};
+
+use super::printer::{self, Piece};
+use super::Expr;
+use crate::compiler::Chunk;
+use crate::scanner::Token;
+
+/// A function declaration. Every closure created from it shares it rather than
+/// copying the body.
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    // The body compiled for the VM backend; see `Function::chunk`.
+    pub(crate) chunk: OnceLock<Chunk>,
+}
+
+// Just the name, so compiled code that refers to a declaration stays readable.
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        printer::write(Piece::Function(self), f)
+    }
+}
+
+pub enum Stmt {
+    Block {
+        statements: Vec<Stmt>,
+    },
+    Class {
+        name: Token,
+        methods: Vec<Arc<Function>>,
+    },
+    Expression {
+        expression: Expr,
+    },
+    Function(Arc<Function>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    Print {
+        expression: Expr,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Var {
+        name: Token,
+        initializer: Option<Expr>,
+    },
+    While {
+        // The `while` or `for` keyword, for the line of errors raised by the loop.
+        keyword: Token,
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+}
+
+// Statements print in the same parenthesized style as expressions, one per line.
+impl Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        printer::write(Piece::Stmt(self), f)
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use crate::parser::{Expr, ExprId, Function, ParseError, Stmt};
+use crate::scanner::Token;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+}
+
+/// How many scopes out from its use each local variable reference is declared.
+/// References not here are globals.
+pub type Locals = HashMap<ExprId, usize>;
+
+/// A pass between parsing and running that works out which declaration each local
+/// variable reference refers to, for the interpreter to take on before running the
+/// program. It also reports the mistakes that can be found without running anything.
+pub struct Resolver {
+    locals: Locals,
+    // Local scopes, innermost last. Each name maps to whether its initializer has
+    // finished. Globals aren't tracked.
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<ParseError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            locals: Locals::new(),
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn resolve(mut self, statements: &[Stmt]) -> Result<Locals, Vec<ParseError>> {
+        self.resolve_statements(statements);
+        self.finish()
+    }
+
+    /// Resolves an expression on its own, like a line echoed by the REPL.
+    pub fn resolve_lone_expression(mut self, expression: &Expr) -> Result<Locals, Vec<ParseError>> {
+        self.resolve_expression(expression);
+        self.finish()
+    }
+
+    fn finish(self) -> Result<Locals, Vec<ParseError>> {
+        if self.errors.is_empty() {
+            Ok(self.locals)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            Stmt::Class { name, methods } => {
+                let enclosing = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(name);
+                self.define(name);
+
+                // Methods are bound in a scope of their own that holds `this`.
+                self.begin_scope();
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert("this".to_string(), true);
+                for method in methods {
+                    let function_type = match method.name.lexeme.as_str() {
+                        "init" => FunctionType::Initializer,
+                        _ => FunctionType::Method,
+                    };
+                    self.resolve_function(method, function_type);
+                }
+                self.end_scope();
+
+                self.current_class = enclosing;
+            }
+            Stmt::Expression { expression } | Stmt::Print { expression } => {
+                self.resolve_expression(expression)
+            }
+            Stmt::Function(function) => {
+                // Defined before the body is resolved so the function can call itself.
+                self.declare(&function.name);
+                self.define(&function.name);
+                self.resolve_function(function, FunctionType::Function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+                if let Some(value) = value {
+                    // A bare `return;` is allowed, and returns the instance.
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(keyword, "Can't return a value from an initializer.");
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, function: &Function, function_type: FunctionType) {
+        let enclosing = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+
+        self.current_function = enclosing;
+    }
+
+    // Expressions don't introduce scopes, so the order they're visited in doesn't
+    // matter. Like evaluation, this uses an explicit stack rather than recursion.
+    fn resolve_expression(&mut self, expression: &Expr) {
+        let mut pending = vec![expression];
+
+        while let Some(expr) = pending.pop() {
+            match expr {
+                Expr::Assign { id, name, value } => {
+                    pending.push(value);
+                    self.resolve_local(*id, name);
+                }
+                Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                    pending.push(left);
+                    pending.push(right);
+                }
+                Expr::Call {
+                    callee, arguments, ..
+                } => {
+                    pending.push(callee);
+                    pending.extend(arguments);
+                }
+                Expr::Get { object, .. } => pending.push(object),
+                Expr::Grouping { expression } => pending.push(expression),
+                Expr::Index { object, index, .. } => {
+                    pending.push(object);
+                    pending.push(index);
+                }
+                Expr::Set { object, value, .. } => {
+                    pending.push(object);
+                    pending.push(value);
+                }
+                Expr::Literal { .. } => {}
+                Expr::This { id, keyword } => {
+                    if self.current_class == ClassType::None {
+                        self.error(keyword, "Can't use 'this' outside of a class.");
+                    }
+                    self.resolve_local(*id, keyword);
+                }
+                Expr::Unary { right, .. } => pending.push(right),
+                Expr::Variable { id, name } => {
+                    let declared = self.scopes.last().and_then(|scope| scope.get(&name.lexeme));
+                    if declared == Some(&false) {
+                        self.error(name, "Can't read local variable in its own initializer.");
+                    }
+                    self.resolve_local(*id, name);
+                }
+            }
+        }
+    }
+
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                self.locals.insert(id, depth);
+                return;
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.contains_key(&name.lexeme) {
+            self.error(name, "Already a variable with this name in this scope.");
+            return;
+        }
+        scope.insert(name.lexeme.clone(), false);
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors.push(ParseError {
+            source: token.source.clone(),
+            line: token.line,
+            message: message.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use crate::lox::{self, LoxError};
+
+    fn errors(source: &str) -> String {
+        match Interpreter::default().eval(source) {
+            Err(e @ LoxError::Parse(_)) => e.to_string(),
+            _ => panic!("expected resolver errors"),
+        }
+    }
+
+    #[test]
+    fn test_closure_sees_declaration_not_later_shadow() {
+        let mut interpreter = Interpreter::default();
+        let source = "var a = \"global\";\n\
+                      var seen = \"\";\n\
+                      {\n\
+                        fun show() { seen = seen + a + \" \"; }\n\
+                        show();\n\
+                        var a = \"block\";\n\
+                        show();\n\
+                      }";
+        interpreter.eval(source).ok().unwrap();
+        assert_eq!(
+            interpreter.eval("seen").ok().unwrap(),
+            "global global ".into()
+        );
+    }
+
+    #[test]
+    fn test_locals_resolve_through_nested_functions() {
+        let mut interpreter = Interpreter::default();
+        let source = "fun outer() {\n\
+                        var x = 1;\n\
+                        fun middle() { fun inner() { x = x + 1; return x; } return inner; }\n\
+                        return middle();\n\
+                      }\n\
+                      var step = outer();\n\
+                      step();";
+        interpreter.eval(source).ok().unwrap();
+        assert_eq!(interpreter.eval("step()").ok().unwrap(), 3i64.into());
+    }
+
+    #[test]
+    fn test_static_errors() {
+        assert_eq!(
+            errors("{ var a = 1; { var a = a; } }"),
+            "<eval>:1: Error: Can't read local variable in its own initializer."
+        );
+        assert_eq!(
+            errors("fun f(a, a) {}\n{ var b; var b; }"),
+            "<eval>:1: Error: Already a variable with this name in this scope.\n\
+             <eval>:2: Error: Already a variable with this name in this scope."
+        );
+        assert_eq!(
+            errors("print 1;\nreturn 2;"),
+            "<eval>:2: Error: Can't return from top-level code."
+        );
+        assert_eq!(
+            errors("class A {\n  init() { return 1; }\n  other() { return 2; }\n}"),
+            "<eval>:2: Error: Can't return a value from an initializer."
+        );
+        assert_eq!(
+            errors("print this;\nfun f() { return this; }\nclass A { m() { return this; } }"),
+            "<eval>:1: Error: Can't use 'this' outside of a class.\n\
+             <eval>:2: Error: Can't use 'this' outside of a class."
+        );
+    }
+
+    #[test]
+    fn test_front_end_needs_no_interpreter() {
+        let source = "var g = 1;\n{ var a = 2; { print a + g; } }";
+        let program = lox::compile(source.to_string(), "<test>").ok().unwrap();
+        let depths: Vec<usize> = program.locals.values().copied().collect();
+        assert_eq!(depths, [1]);
+
+        let errors = lox::compile("print 1 @;\nreturn;".to_string(), "<test>")
+            .err()
+            .unwrap();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["Unexpected character: @"]);
+
+        let errors = lox::compile("{ var a; var a; }\nreturn;".to_string(), "<test>")
+            .err()
+            .unwrap();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [1, 2]);
+    }
+
+    #[test]
+    fn test_globals_can_be_redeclared() {
+        let mut interpreter = Interpreter::default();
+        let value = interpreter.eval("var a = 1; var a = a + 1; fun f() { return a; }");
+        assert!(value.is_ok());
+        assert_eq!(interpreter.eval("f()").ok().unwrap(), 2i64.into());
+    }
+}
@@ -1,10 +1,11 @@
 use phf::phf_map;
-use std::{any::Any, fmt::Display, rc::Rc};
+use std::fmt::Display;
+use std::sync::Arc;
 
 mod token;
 
 pub use self::token::{Token, TokenType};
-use crate::lox;
+use crate::value::Value;
 
 static KEYWORDS: phf::Map<&'static str, TokenType> = phf_map! {
     "and" => TokenType::And,
@@ -25,8 +26,15 @@ static KEYWORDS: phf::Map<&'static str, TokenType> = phf_map! {
     "while" => TokenType::While,
 };
 
-struct ScanError {
-    message: String,
+pub fn keywords() -> impl Iterator<Item = &'static str> {
+    KEYWORDS.keys().copied()
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    pub source: Arc<str>,
+    pub line: usize,
+    pub message: String,
 }
 
 impl Display for ScanError {
@@ -36,19 +44,21 @@ impl Display for ScanError {
 }
 
 pub struct Scanner {
-    source: String,
+    name: Arc<str>,
     source_chars: Vec<char>,
     tokens: Vec<Token>,
+    errors: Vec<ScanError>,
     start: usize,
     current: usize,
     line: usize,
 }
 
 impl Scanner {
-    pub fn new(source: String) -> Scanner {
+    pub fn new(source: String, name: &str) -> Scanner {
         Scanner {
-            source: source.clone(),
+            name: name.into(),
             tokens: Vec::new(),
+            errors: Vec::new(),
             source_chars: source.chars().collect(),
             start: 0,
             current: 0,
@@ -62,25 +72,34 @@ impl Scanner {
             match self.scan_token() {
                 Ok(Some(token)) => self.tokens.push(token),
                 Ok(None) => (),
-                Err(e) => {
-                    lox::error(self.line, &e.message);
-                }
+                Err(e) => self.errors.push(e),
             }
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Eof, String::new(), None, self.line));
+        self.tokens.push(Token::new(
+            TokenType::Eof,
+            String::new(),
+            None,
+            self.line,
+            self.name.clone(),
+        ));
 
         &self.tokens
     }
 
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
     fn scan_token(&mut self) -> Result<Option<Token>, ScanError> {
-        if let Some(c) = self.advance() {
+        if let Some(&c) = self.advance() {
             match c {
                 '(' => Ok(Some(self.empty_token(TokenType::LeftParen))),
                 ')' => Ok(Some(self.empty_token(TokenType::RightParen))),
                 '{' => Ok(Some(self.empty_token(TokenType::LeftBrace))),
                 '}' => Ok(Some(self.empty_token(TokenType::RightBrace))),
+                '[' => Ok(Some(self.empty_token(TokenType::LeftBracket))),
+                ']' => Ok(Some(self.empty_token(TokenType::RightBracket))),
                 ',' => Ok(Some(self.empty_token(TokenType::Comma))),
                 '.' => Ok(Some(self.empty_token(TokenType::Dot))),
                 '-' => Ok(Some(self.empty_token(TokenType::Minus))),
@@ -134,16 +153,23 @@ impl Scanner {
                     self.line += 1;
                     Ok(None)
                 }
-                '"' => Ok(Some(self.parse_string()?)),
+                '"' => {
+                    if *self.peek() == '"' && *self.peek_next() == '"' {
+                        self.current += 2;
+                        Ok(Some(self.parse_triple_string()?))
+                    } else {
+                        Ok(Some(self.parse_string()?))
+                    }
+                }
                 _ => {
-                    if c.is_numeric() {
-                        Ok(Some(self.parse_number()))
+                    // Only ASCII digits start a number; other Unicode digits such as
+                    // '²' can't be parsed as one.
+                    if c.is_ascii_digit() {
+                        Ok(Some(self.parse_number()?))
                     } else if c.is_alphabetic() {
                         Ok(Some(self.parse_identifier()))
                     } else {
-                        Err(ScanError {
-                            message: format!("Unexpected character: {}", c),
-                        })
+                        Err(self.error(&format!("Unexpected character: {}", c)))
                     }
                 }
             }
@@ -183,7 +209,113 @@ impl Scanner {
     }
 
     fn parse_string(&mut self) -> Result<Token, ScanError> {
+        let mut value = String::new();
+        // Keep scanning to the closing quote after a bad escape so the rest of the
+        // string isn't scanned as code.
+        let mut escape_error = None;
+
         while *self.peek() != '"' && !self.is_at_end() {
+            let c = *self.peek();
+            if c == '\n' {
+                self.line += 1;
+            }
+            self.advance();
+
+            if c == '\\' && !self.is_at_end() {
+                match self.escape() {
+                    Ok(escaped) => value.push(escaped),
+                    Err(e) => {
+                        escape_error.get_or_insert(e);
+                    }
+                }
+            } else {
+                value.push(c);
+            }
+        }
+
+        if self.is_at_end() {
+            return Err(self.error("Unterminated string."));
+        }
+
+        // Consume closing '"'
+        self.advance();
+
+        match escape_error {
+            Some(e) => Err(e),
+            None => Ok(self.token(TokenType::String, Some(Value::String(value)))),
+        }
+    }
+
+    // Called with the backslash consumed. Unknown escapes leave the following
+    // character in place to be scanned as part of the string.
+    fn escape(&mut self) -> Result<char, ScanError> {
+        let escaped = match *self.peek() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            'u' => {
+                self.advance();
+                return self.unicode_escape();
+            }
+            c => return Err(self.error(&format!("Invalid escape sequence '\\{}'.", c))),
+        };
+        self.advance();
+        Ok(escaped)
+    }
+
+    // Parses the `{XXXX}` part of a `\u{XXXX}` escape.
+    fn unicode_escape(&mut self) -> Result<char, ScanError> {
+        if !self.is_match('{') {
+            return Err(self.error("Expect '{' after '\\u'."));
+        }
+
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() {
+            digits.push(*self.peek());
+            self.advance();
+        }
+
+        if *self.peek() != '}' {
+            return Err(match *self.peek() {
+                '"' | '\0' => self.error("Unterminated unicode escape."),
+                c => self.error(&format!("Invalid hex digit '{}' in unicode escape.", c)),
+            });
+        }
+        self.advance();
+
+        if digits.is_empty() {
+            return Err(self.error("Empty unicode escape."));
+        }
+        if digits.len() > 6 {
+            return Err(self.error("Unicode escape has more than 6 hex digits."));
+        }
+
+        let code = u32::from_str_radix(&digits, 16).unwrap();
+        if code > 0x10FFFF {
+            return Err(self.error(&format!("Unicode escape '{}' is out of range.", digits)));
+        }
+        char::from_u32(code).ok_or_else(|| {
+            self.error(&format!(
+                "Unicode escape '{}' is a surrogate code point.",
+                digits
+            ))
+        })
+    }
+
+    // Triple-quoted strings are raw: everything up to the closing `"""` is kept as
+    // written, except for a newline directly after the opener.
+    fn parse_triple_string(&mut self) -> Result<Token, ScanError> {
+        let opening_line = self.line;
+        if *self.peek() == '\n' {
+            self.line += 1;
+            self.advance();
+        }
+        let content_start = self.current;
+
+        while !self.is_triple_quote() && !self.is_at_end() {
             if *self.peek() == '\n' {
                 self.line += 1;
             }
@@ -192,34 +324,53 @@ impl Scanner {
 
         if self.is_at_end() {
             return Err(ScanError {
+                source: self.name.clone(),
+                line: opening_line,
                 message: "Unterminated string.".to_string(),
             });
         }
 
-        // Consume closing '"'
-        self.advance();
+        let value: String = self.source_chars[content_start..self.current]
+            .iter()
+            .collect();
+
+        // Consume closing '"""'
+        self.current += 3;
 
-        let value = &self.source[self.start + 1..self.current - 1];
-        Ok(self.token(TokenType::String, Some(Rc::new(value.to_string()))))
+        Ok(self.token(TokenType::String, Some(Value::String(value))))
     }
 
-    fn parse_number(&mut self) -> Token {
-        while self.peek().is_numeric() {
+    fn is_triple_quote(&self) -> bool {
+        self.source_chars.get(self.current..self.current + 3) == Some(&['"'; 3])
+    }
+
+    fn parse_number(&mut self) -> Result<Token, ScanError> {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // Look for fractional part
-        if *self.peek() == '.' && self.peek_next().is_numeric() {
+        if *self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // Consume '.'
             self.advance();
 
-            while self.peek().is_numeric() {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
+
+            let s = &self.lexeme();
+            return match s.parse::<f64>() {
+                Ok(n) => Ok(self.token(TokenType::Number, Some(Value::Number(n)))),
+                Err(_) => Err(self.error("Invalid number literal.")),
+            };
         }
 
-        let s = self.source.get(self.start..self.current).unwrap();
-        self.token(TokenType::Number, Some(Rc::new(s.parse::<f64>().unwrap())))
+        // Without a fractional part the literal is an integer.
+        let s = &self.lexeme();
+        match s.parse::<i64>() {
+            Ok(i) => Ok(self.token(TokenType::Number, Some(Value::Int(i)))),
+            Err(_) => Err(self.error("Integer literal is too large.")),
+        }
     }
 
     fn parse_identifier(&mut self) -> Token {
@@ -227,7 +378,7 @@ impl Scanner {
             self.advance();
         }
 
-        let text = self.source.get(self.start..self.current).unwrap();
+        let text = &self.lexeme();
         let token_type = match KEYWORDS.get(text) {
             Some(t) => t,
             None => &TokenType::Identifier,
@@ -240,14 +391,27 @@ impl Scanner {
         self.token(token_type, None)
     }
 
-    fn token(&mut self, token_type: TokenType, literal: Option<Rc<dyn Any>>) -> Token {
-        let text = self
-            .source
-            .get(self.start..self.current)
-            .unwrap()
-            .to_string();
+    fn token(&mut self, token_type: TokenType, literal: Option<Value>) -> Token {
+        Token::new(
+            token_type,
+            self.lexeme(),
+            literal,
+            self.line,
+            self.name.clone(),
+        )
+    }
 
-        Token::new(token_type, text, literal, self.line)
+    // Indices count chars, not bytes, so slice `source_chars`.
+    fn lexeme(&self) -> String {
+        self.source_chars[self.start..self.current].iter().collect()
+    }
+
+    fn error(&self, message: &str) -> ScanError {
+        ScanError {
+            source: self.name.clone(),
+            line: self.line,
+            message: message.to_string(),
+        }
     }
 }
 
@@ -257,79 +421,241 @@ mod tests {
 
     #[test]
     fn test_scan_tokens() {
-        let mut scanner = Scanner::new("-123 * 45.67".to_string());
+        let mut scanner = Scanner::new("-123 * 45.67".to_string(), "<test>");
         let tokens = scanner.scan_tokens();
         assert_eq!(
             tokens[0],
-            Token::new(TokenType::Minus, "-".to_string(), None, 1)
+            Token::new(TokenType::Minus, "-".to_string(), None, 1, "<test>".into())
         );
         assert_eq!(
             tokens[1],
             Token::new(
                 TokenType::Number,
                 "123".to_string(),
-                Some(Rc::new(123.0)),
-                1
+                Some(Value::Int(123)),
+                1,
+                "<test>".into()
             )
         );
         assert_eq!(
             tokens[2],
-            Token::new(TokenType::Star, "*".to_string(), None, 1)
+            Token::new(TokenType::Star, "*".to_string(), None, 1, "<test>".into())
         );
         assert_eq!(
             tokens[3],
             Token::new(
                 TokenType::Number,
                 "45.67".to_string(),
-                Some(Rc::new(45.67)),
-                1
+                Some(Value::Number(45.67)),
+                1,
+                "<test>".into()
             )
         );
         assert_eq!(
             tokens[4],
-            Token::new(TokenType::Eof, "".to_string(), None, 1)
+            Token::new(TokenType::Eof, "".to_string(), None, 1, "<test>".into())
         );
     }
 
     #[test]
     fn test_scan_string() {
-        let mut scanner = Scanner::new("\"hello\"".to_string());
+        let mut scanner = Scanner::new("\"hello\"".to_string(), "<test>");
         let tokens = scanner.scan_tokens();
         assert_eq!(
             tokens[0],
             Token::new(
                 TokenType::String,
                 "\"hello\"".to_string(),
-                Some(Rc::new("hello".to_string())),
-                1
+                Some(Value::String("hello".to_string())),
+                1,
+                "<test>".into()
             )
         );
         assert_eq!(
             tokens[1],
-            Token::new(TokenType::Eof, "".to_string(), None, 1)
+            Token::new(TokenType::Eof, "".to_string(), None, 1, "<test>".into())
+        );
+    }
+
+    fn scan_string(source: &str) -> Result<String, String> {
+        let mut scanner = Scanner::new(source.to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        match scanner.errors().first() {
+            Some(e) => Err(e.message.clone()),
+            None => match &tokens[0].literal {
+                Some(Value::String(s)) => Ok(s.clone()),
+                other => panic!("expected a string literal, got {:?}", other),
+            },
+        }
+    }
+
+    #[test]
+    fn test_scan_escapes() {
+        assert_eq!(
+            scan_string(r#""a\tb\n\"c\" \\""#),
+            Ok("a\tb\n\"c\" \\".to_string())
+        );
+        assert_eq!(
+            scan_string(r#""\q""#),
+            Err("Invalid escape sequence '\\q'.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scan_unicode_escapes() {
+        assert_eq!(scan_string(r#""caf\u{e9}""#), Ok("café".to_string()));
+        assert_eq!(scan_string(r#""\u{1F600}!""#), Ok("😀!".to_string()));
+
+        let mut scanner = Scanner::new(r#""\u{1F600}""#.to_string(), "<test>");
+        let token = &scanner.scan_tokens()[0];
+        assert_eq!(token.lexeme, r#""\u{1F600}""#);
+        match &token.literal {
+            Some(Value::String(s)) => assert_eq!(s.chars().count(), 1),
+            other => panic!("expected a string literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_scan_malformed_unicode_escapes() {
+        let cases = [
+            (r#""\u1F600""#, "Expect '{' after '\\u'."),
+            (r#""\u{}""#, "Empty unicode escape."),
+            (r#""\u{12G4}""#, "Invalid hex digit 'G' in unicode escape."),
+            (r#""\u{41""#, "Unterminated unicode escape."),
+            (
+                r#""\u{1234567}""#,
+                "Unicode escape has more than 6 hex digits.",
+            ),
+            (
+                r#""\u{110000}""#,
+                "Unicode escape '110000' is out of range.",
+            ),
+            (
+                r#""\u{D800}""#,
+                "Unicode escape 'D800' is a surrogate code point.",
+            ),
+        ];
+        for (source, message) in cases {
+            assert_eq!(scan_string(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_scan_bad_escape_consumes_rest_of_string() {
+        let mut scanner = Scanner::new("\"\\u{zz} + 1\" + 2".to_string(), "<test>");
+        let tokens = scanner.scan_tokens();
+        assert_eq!(tokens.len(), 3);
+        assert!(matches!(tokens[0].token_type, TokenType::Plus));
+        assert_eq!(scanner.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_scan_non_ascii_source() {
+        let mut scanner = Scanner::new("\"é\" + \"ü\"".to_string(), "<test>");
+        let tokens = scanner.scan_tokens();
+        assert_eq!(tokens[0].lexeme, "\"é\"");
+        assert_eq!(tokens[2].literal, Some(Value::String("ü".to_string())));
+    }
+
+    #[test]
+    fn test_scan_triple_quoted_string() {
+        let source = "\"\"\"\nfirst\n  second\nthird\"\"\" +\n1";
+        let mut scanner = Scanner::new(source.to_string(), "<test>");
+        let tokens = scanner.scan_tokens();
+        assert_eq!(
+            tokens[0].literal,
+            Some(Value::String("first\n  second\nthird".to_string()))
+        );
+        assert_eq!(tokens[0].line, 4);
+        assert_eq!(tokens[1].line, 4);
+        assert_eq!(tokens[2].line, 5);
+    }
+
+    #[test]
+    fn test_scan_triple_quoted_string_is_raw() {
+        let source = r#""""say "hi" or ""hey"" \n""" """""""#;
+        let mut scanner = Scanner::new(source.to_string(), "<test>");
+        let tokens = scanner.scan_tokens();
+        assert_eq!(
+            tokens[0].literal,
+            Some(Value::String(r#"say "hi" or ""hey"" \n"#.to_string()))
+        );
+        assert_eq!(tokens[1].literal, Some(Value::String(String::new())));
+    }
+
+    #[test]
+    fn test_scan_unterminated_triple_quoted_string() {
+        let mut scanner = Scanner::new("1\n\"\"\"open\n\"\"\n".to_string(), "<test>");
+        scanner.scan_tokens();
+        assert_eq!(scanner.errors()[0].message, "Unterminated string.");
+        assert_eq!(scanner.errors()[0].line, 2);
+    }
+
+    #[test]
+    fn test_scan_int_and_float_literals() {
+        let mut scanner = Scanner::new("7 7.0 7.5".to_string(), "<test>");
+        let tokens = scanner.scan_tokens();
+        assert!(matches!(tokens[0].literal, Some(Value::Int(7))));
+        assert!(matches!(tokens[1].literal, Some(Value::Number(n)) if n == 7.0));
+        assert!(matches!(tokens[2].literal, Some(Value::Number(n)) if n == 7.5));
+    }
+
+    #[test]
+    fn test_scan_int_literal_too_large() {
+        let mut scanner = Scanner::new("99999999999999999999".to_string(), "<test>");
+        scanner.scan_tokens();
+        assert_eq!(scanner.errors()[0].message, "Integer literal is too large.");
+    }
+
+    #[test]
+    fn test_scan_non_ascii_digits() {
+        let mut scanner = Scanner::new("print ²;\nprint 1.²;".to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        let messages: Vec<&str> = scanner
+            .errors()
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            ["Unexpected character: ²", "Unexpected character: ²"]
         );
+        assert!(matches!(tokens[3].literal, Some(Value::Int(1))));
+        assert!(matches!(tokens[4].token_type, TokenType::Dot));
     }
 
     // Test for scanning with comments
     #[test]
     fn test_scan_tokens_with_comments() {
-        let mut scanner = Scanner::new("1 + 2 // 3 + 4".to_string());
+        let mut scanner = Scanner::new("1 + 2 // 3 + 4".to_string(), "<test>");
         let tokens = scanner.scan_tokens();
         assert_eq!(
             tokens[0],
-            Token::new(TokenType::Number, "1".to_string(), Some(Rc::new(1.0)), 1)
+            Token::new(
+                TokenType::Number,
+                "1".to_string(),
+                Some(Value::Int(1)),
+                1,
+                "<test>".into()
+            )
         );
         assert_eq!(
             tokens[1],
-            Token::new(TokenType::Plus, "+".to_string(), None, 1)
+            Token::new(TokenType::Plus, "+".to_string(), None, 1, "<test>".into())
         );
         assert_eq!(
             tokens[2],
-            Token::new(TokenType::Number, "2".to_string(), Some(Rc::new(2.0)), 1)
+            Token::new(
+                TokenType::Number,
+                "2".to_string(),
+                Some(Value::Int(2)),
+                1,
+                "<test>".into()
+            )
         );
         assert_eq!(
             tokens[3],
-            Token::new(TokenType::Eof, "".to_string(), None, 1)
+            Token::new(TokenType::Eof, "".to_string(), None, 1, "<test>".into())
         );
     }
 }
@@ -1,28 +1,33 @@
-use std::any::Any;
 use std::fmt::Debug;
 use std::mem::discriminant;
-use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::value::Value;
 
 #[derive(Clone, Debug)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
-    pub literal: Option<Rc<dyn Any>>,
+    pub literal: Option<Value>,
     pub line: usize,
+    // Name of the file (or `<repl>`, `<eval>`) the token was scanned from.
+    pub source: Arc<str>,
 }
 
 impl Token {
     pub fn new(
         token_type: TokenType,
         lexeme: String,
-        literal: Option<Rc<dyn Any>>,
+        literal: Option<Value>,
         line: usize,
+        source: Arc<str>,
     ) -> Token {
         Token {
             token_type,
             lexeme,
             literal,
             line,
+            source,
         }
     }
 }
@@ -51,6 +56,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
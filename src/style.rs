@@ -0,0 +1,90 @@
+use std::env;
+
+const RESET: &str = "\x1b[0m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const DIM: &str = "\x1b[2m";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorChoice {
+    // Color when writing to a terminal, unless NO_COLOR is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        self.enabled_with(is_terminal, env::var_os("NO_COLOR").is_some())
+    }
+
+    // `enabled` with the NO_COLOR lookup already done, so tests don't touch the environment.
+    fn enabled_with(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && !no_color,
+        }
+    }
+}
+
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Formats a diagnostic as `source:line: Label: message`. Warnings are labelled in
+/// yellow and everything else in red, with the location dimmed.
+pub fn diagnostic(location: &str, label: &str, message: &str, color: bool) -> String {
+    let label_style = if label == "Warning" {
+        BOLD_YELLOW
+    } else {
+        BOLD_RED
+    };
+    format!(
+        "{}: {}: {}",
+        paint(location, DIM, color),
+        paint(label, label_style, color),
+        message
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_diagnostic() {
+        assert_eq!(
+            diagnostic("main.lox:3", "Error", "Expect expression.", false),
+            "main.lox:3: Error: Expect expression."
+        );
+    }
+
+    #[test]
+    fn test_colored_diagnostic() {
+        assert_eq!(
+            diagnostic("main.lox:3", "RuntimeError", "Oops.", true),
+            "\x1b[2mmain.lox:3\x1b[0m: \x1b[1;31mRuntimeError\x1b[0m: Oops."
+        );
+        assert!(diagnostic("main.lox:3", "Warning", "Hmm.", true).contains("\x1b[1;33mWarning"));
+    }
+
+    #[test]
+    fn test_color_choice() {
+        assert!(ColorChoice::Always.enabled(false));
+        assert!(!ColorChoice::Never.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+    }
+
+    #[test]
+    fn test_no_color() {
+        assert!(ColorChoice::Auto.enabled_with(true, false));
+        assert!(!ColorChoice::Auto.enabled_with(true, true));
+        assert!(ColorChoice::Always.enabled_with(true, true));
+        assert!(ColorChoice::Always.enabled_with(false, true));
+    }
+}
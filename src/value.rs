@@ -0,0 +1,313 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::function::LoxFunction;
+use crate::native::NativeFunction;
+use crate::parser::RuntimeError;
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    // Integer literals and arithmetic between integers.
+    Int(i64),
+    Number(f64),
+    String(String),
+    Function(Arc<LoxFunction>),
+    Native(Arc<NativeFunction>),
+    Class(Arc<LoxClass>),
+    Instance(Arc<LoxInstance>),
+    Array(Arc<Vec<Value>>),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            // Compared exactly, so large ints aren't equal to a nearby rounded float.
+            (Value::Int(i), Value::Number(n)) | (Value::Number(n), Value::Int(i)) => {
+                n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 && *n as i64 == *i
+            }
+            (Value::String(a), Value::String(b)) => a == b,
+            // Functions, natives, classes, instances and arrays are equal only to themselves.
+            (Value::Function(a), Value::Function(b)) => Arc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Arc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Arc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Arc::ptr_eq(a, b),
+            (Value::Array(a), Value::Array(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Int(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::Array(_) => "array",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::Number(_))
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(v) => v.into(),
+            None => Value::Nil,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub actual: &'static str,
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected {} but got {}.", self.expected, self.actual)
+    }
+}
+
+impl From<ConversionError> for RuntimeError {
+    fn from(e: ConversionError) -> Self {
+        RuntimeError::native(&e.to_string())
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value.as_f64() {
+            Some(n) => Ok(n),
+            None => Err(ConversionError {
+                expected: "number",
+                actual: value.type_name(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = ConversionError;
+
+    // Only whole numbers in range convert; anything else would be silently truncated.
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(i) => Ok(i),
+            Value::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                Ok(n as i64)
+            }
+            _ => Err(ConversionError {
+                expected: "integer",
+                actual: value.type_name(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(ConversionError {
+                expected: "boolean",
+                actual: value.type_name(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            _ => Err(ConversionError {
+                expected: "string",
+                actual: value.type_name(),
+            }),
+        }
+    }
+}
+
+/// Typed access to the arguments of a native function.
+pub trait Args {
+    fn get_as<T>(&self, index: usize) -> Result<T, RuntimeError>
+    where
+        T: TryFrom<Value, Error = ConversionError>;
+
+    fn get_number(&self, index: usize) -> Result<f64, RuntimeError> {
+        self.get_as(index)
+    }
+
+    fn get_integer(&self, index: usize) -> Result<i64, RuntimeError> {
+        self.get_as(index)
+    }
+
+    fn get_bool(&self, index: usize) -> Result<bool, RuntimeError> {
+        self.get_as(index)
+    }
+
+    fn get_string(&self, index: usize) -> Result<String, RuntimeError> {
+        self.get_as(index)
+    }
+}
+
+impl Args for [Value] {
+    fn get_as<T>(&self, index: usize) -> Result<T, RuntimeError>
+    where
+        T: TryFrom<Value, Error = ConversionError>,
+    {
+        let value = <[Value]>::get(self, index)
+            .cloned()
+            .ok_or_else(|| RuntimeError::native(&format!("Missing argument {}.", index + 1)))?;
+
+        T::try_from(value).map_err(|e| {
+            RuntimeError::native(&format!(
+                "Expected {} for argument {} but got {}.",
+                e.expected,
+                index + 1,
+                e.actual
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_round_trip() {
+        let value = Value::from(2.5);
+        assert_eq!(value, Value::Number(2.5));
+        assert_eq!(f64::try_from(value), Ok(2.5));
+    }
+
+    #[test]
+    fn test_integer_round_trip() {
+        let value = Value::from(42i64);
+        assert!(matches!(value, Value::Int(42)));
+        assert_eq!(i64::try_from(value), Ok(42));
+        assert_eq!(f64::try_from(Value::from(42i64)), Ok(42.0));
+        assert_eq!(i64::try_from(Value::from(-7.0)), Ok(-7));
+    }
+
+    #[test]
+    fn test_integer_rejects_fraction() {
+        let err = i64::try_from(Value::from(1.5)).unwrap_err();
+        assert_eq!(err.to_string(), "Expected integer but got number.");
+        assert!(i64::try_from(Value::from(f64::NAN)).is_err());
+        assert!(i64::try_from(Value::from(1e300)).is_err());
+    }
+
+    #[test]
+    fn test_bool_round_trip() {
+        let value = Value::from(true);
+        assert_eq!(value, Value::Bool(true));
+        assert_eq!(bool::try_from(value), Ok(true));
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let value = Value::from("hi");
+        assert_eq!(value, Value::String("hi".to_string()));
+        assert_eq!(String::try_from(value), Ok("hi".to_string()));
+        assert_eq!(
+            String::try_from(Value::from("owned".to_string())),
+            Ok("owned".to_string())
+        );
+    }
+
+    #[test]
+    fn test_option_conversion() {
+        assert_eq!(Value::from(None::<f64>), Value::Nil);
+        assert_eq!(Value::from(Some("x")), Value::from("x"));
+    }
+
+    #[test]
+    fn test_conversion_error_names_types() {
+        let err = f64::try_from(Value::from("3")).unwrap_err();
+        assert_eq!(err.to_string(), "Expected number but got string.");
+        let err = bool::try_from(Value::Nil).unwrap_err();
+        assert_eq!(err.to_string(), "Expected boolean but got nil.");
+    }
+
+    #[test]
+    fn test_args() {
+        let args = [Value::from(1.0), Value::from("two"), Value::from(false)];
+        assert_eq!(args.get_number(0).ok(), Some(1.0));
+        assert_eq!(args.get_string(1).ok(), Some("two".to_string()));
+        assert_eq!(args.get_bool(2).ok(), Some(false));
+        assert_eq!(
+            args.get_number(1).err().unwrap().message(),
+            "Expected number for argument 2 but got string."
+        );
+        assert_eq!(
+            args.get_number(3).err().unwrap().message(),
+            "Missing argument 4."
+        );
+    }
+}
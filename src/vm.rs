@@ -0,0 +1,160 @@
+use crate::compiler::{Chunk, OpCode};
+use crate::interpreter::Interpreter;
+use crate::parser::{Expr, RuntimeError};
+use crate::value::Value;
+
+/// Runs `chunk` and returns its result: the value of a compiled expression, or
+/// what a compiled body returns. Scopes pushed by the chunk aren't popped if it
+/// stops early; the caller restores the scope it ran in.
+///
+/// Operators, variables and calls go through the same functions the tree-walker
+/// uses, so both backends agree on values and error messages.
+pub fn run(chunk: &Chunk, interpreter: &mut Interpreter) -> Result<Value, RuntimeError> {
+    let mut stack: Vec<Value> = Vec::new();
+    let mut ip = 0;
+
+    while let Some(op) = chunk.code.get(ip) {
+        ip += 1;
+        match op {
+            OpCode::Constant(index) => stack.push(chunk.constants[*index].clone()),
+            OpCode::CheckNumber(operator) => {
+                Expr::number_operand(stack.last().unwrap(), operator, "Operands must be numbers.")?;
+            }
+            OpCode::Unary(operator) => {
+                let right = stack.pop().unwrap();
+                stack.push(Expr::unary(operator, right)?);
+            }
+            OpCode::Binary(operator) => {
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                stack.push(Expr::binary(left, operator, right, interpreter)?);
+            }
+            OpCode::GetVariable { name, id } => {
+                stack.push(interpreter.look_up_variable(name, *id)?)
+            }
+            OpCode::SetVariable { name, id } => {
+                interpreter.assign_variable(name, *id, stack.last().unwrap().clone())?
+            }
+            OpCode::Call { paren, count } => {
+                let arguments = stack.split_off(stack.len() - count);
+                let callee = stack.pop().unwrap();
+                stack.push(interpreter.call(callee, arguments, paren)?);
+            }
+            OpCode::GetProperty(name) => {
+                let object = stack.pop().unwrap();
+                stack.push(Expr::get_property(object, name)?);
+            }
+            OpCode::Index(bracket) => {
+                let index = stack.pop().unwrap();
+                let object = stack.pop().unwrap();
+                stack.push(Expr::index(object, bracket, index)?);
+            }
+            OpCode::SetProperty(name) => {
+                let value = stack.pop().unwrap();
+                let object = stack.pop().unwrap();
+                stack.push(Expr::set_property(object, name, value)?);
+            }
+            OpCode::ShortCircuit {
+                when_truthy,
+                target,
+            } => {
+                if stack.last().unwrap().is_truthy() == *when_truthy {
+                    ip = *target;
+                } else {
+                    stack.pop();
+                }
+            }
+            OpCode::Pop => {
+                stack.pop();
+            }
+            OpCode::Print => interpreter.print(stack.pop().unwrap()),
+            OpCode::Step(keyword) => interpreter.step(keyword)?,
+            OpCode::DefineVariable(name) => interpreter.define(&name.lexeme, stack.pop().unwrap()),
+            OpCode::Function(declaration) => interpreter.define_function(declaration),
+            OpCode::Class { name, methods } => interpreter.define_class(name, methods),
+            OpCode::PushScope => interpreter.push_scope(),
+            OpCode::PopScope => interpreter.pop_scope(),
+            OpCode::Jump(target) => ip = *target,
+            OpCode::JumpIfFalse(target) => {
+                if !stack.pop().unwrap().is_truthy() {
+                    ip = *target;
+                }
+            }
+            OpCode::Return => return Ok(stack.pop().unwrap()),
+        }
+    }
+
+    Ok(stack.pop().unwrap_or(Value::Nil))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::interpreter::{stringify, Backend};
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Expr {
+        let mut scanner = Scanner::new(source.to_string(), "<test>");
+        let tokens = scanner.scan_tokens().clone();
+        Parser::new(tokens).parse_expression().unwrap()
+    }
+
+    fn evaluate(source: &str, backend: Backend) -> String {
+        let mut interpreter = Interpreter::builder().backend(backend).build();
+        let result = match backend {
+            Backend::TreeWalker => parse(source).interpret(&mut interpreter),
+            Backend::Vm => run(&compile(&parse(source)), &mut interpreter),
+        };
+        match result {
+            Ok(val) => stringify(val),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let sources = [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "-(4 - 6) / 2",
+            "!nil",
+            "!!0",
+            "\"a\" + \"b\"",
+            "\"count: \" + 3",
+            "\"apple\" < \"banana\"",
+            "1 == 1",
+            "nil != false",
+            "1 + true",
+            "-\"a\"",
+            "\"a\" - (-\"b\")",
+            "nil < 1",
+            "7 / 2",
+            "1 == 1.0",
+            "9223372036854775807 + 1",
+            "nil or \"default\"",
+            "0 or -nil",
+            "false and -nil",
+            "1 and 2 or 3",
+            "nil and 1 or (false or \"last\")",
+            "1 == 2 or 2 == 2",
+            "-nil or true",
+        ];
+
+        for source in sources {
+            assert_eq!(
+                evaluate(source, Backend::TreeWalker),
+                evaluate(source, Backend::Vm),
+                "backends disagree on {}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_vm_long_sum() {
+        let source = vec!["1"; 100_000].join(" + ");
+        assert_eq!(evaluate(&source, Backend::Vm), "100000");
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Blocks until one of a set of files changes. The polling watcher is the only
+/// backend; an event-based one can implement this too.
+pub trait Watcher {
+    fn wait_for_change(&mut self) -> io::Result<()>;
+}
+
+/// The files a script's output depends on. There are no imports yet, so this is
+/// just the script itself.
+pub fn watched_files(entry: &Path) -> Vec<PathBuf> {
+    vec![entry.to_path_buf()]
+}
+
+/// Checks modification times every `interval`.
+pub struct PollingWatcher {
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    seen: Vec<Option<SystemTime>>,
+}
+
+impl PollingWatcher {
+    pub fn new(paths: Vec<PathBuf>, interval: Duration) -> Self {
+        let seen = modified_times(&paths);
+        Self {
+            paths,
+            interval,
+            seen,
+        }
+    }
+}
+
+impl Watcher for PollingWatcher {
+    fn wait_for_change(&mut self) -> io::Result<()> {
+        loop {
+            thread::sleep(self.interval);
+            let current = modified_times(&self.paths);
+            if current != self.seen {
+                self.seen = current;
+                return Ok(());
+            }
+        }
+    }
+}
+
+// A file that's missing (say, mid-save) has no time, so it counts as a change
+// when it disappears and again when it comes back.
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_watched_files_is_entry() {
+        let entry = Path::new("scripts/main.lox");
+        assert_eq!(watched_files(entry), [PathBuf::from("scripts/main.lox")]);
+    }
+
+    #[test]
+    fn test_polling_watcher_sees_change() {
+        let path = env::temp_dir().join(format!("loxide_watch_{}.lox", std::process::id()));
+        fs::write(&path, "1").unwrap();
+        let mut watcher = PollingWatcher::new(vec![path.clone()], Duration::from_millis(10));
+
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                let file = fs::File::options().write(true).open(&path).unwrap();
+                file.set_modified(SystemTime::now() + Duration::from_secs(60))
+                    .unwrap();
+            })
+        };
+        watcher.wait_for_change().unwrap();
+        writer.join().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,506 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("loxide_{}_{}.lox", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn loxide(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_loxide"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn loxide_with_input(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_loxide"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_strict_warns_on_mixed_equality() {
+    let path = script("strict_warn", "print 3 == \"3\";");
+    let output = loxide(&["--strict", path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "false\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{}:1: Warning: Comparing values of different types (number and string) with '=='.\n",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn test_strict_does_not_warn_on_nil_check() {
+    let path = script("strict_nil", "print 3 != nil;");
+    let output = loxide(&["--strict", path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "true\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_default_mode_does_not_warn() {
+    let path = script("default_warn", "print 3 == \"3\";");
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "false\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_default_mode_comparison_error() {
+    let path = script("default_compare", "nil < 1;");
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{}:1: RuntimeError: Operands must be two numbers or two strings.\n",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn test_parse_error_names_file() {
+    let path = script("parse_error", "1 +\n");
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("{}:2: Error: Expect expression.\n", path.display())
+    );
+}
+
+#[test]
+fn test_script_runs_every_statement() {
+    let path = script("statements", "print \"one\";\n1 + 1;\nprint 2 + 1;\n");
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\n3\n");
+
+    // A script doesn't echo its expression statements like the REPL does.
+    let path = script("missing_semicolon", "print 1;\n1 + 1\n");
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{}:3: Error: Expect ';' after expression.\n",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn test_triple_quoted_string_prints_exactly() {
+    let path = script(
+        "triple_quoted",
+        "print \"\"\"\nUsage: tool [options]\n  -h  \"help\"\n\"\"\";",
+    );
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Usage: tool [options]\n  -h  \"help\"\n\n"
+    );
+}
+
+#[test]
+fn test_arguments_after_script_are_passed_through() {
+    let path = script("pass_through", "print args()[0];\nprint args();");
+    let output = loxide(&[path.to_str().unwrap(), "input.txt", "--fast"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "input.txt\n[input.txt, --fast]\n"
+    );
+
+    let output = loxide(&["-e", "args()"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[]\n");
+}
+
+#[test]
+fn test_deep_recursion_fits_the_default_call_depth() {
+    let path = script(
+        "deep_recursion",
+        "fun down(n) { if (n > 0) return down(n - 1); return n; }\n\
+         print down(2999);\n\
+         down(3000);",
+    );
+    for backend in ["--backend=tree", "--backend=vm"] {
+        let output = loxide(&[backend, path.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            format!(
+                "{}:1: RuntimeError: Stack overflow: exceeded 3000 nested calls.\n",
+                path.display()
+            )
+        );
+    }
+}
+
+#[test]
+fn test_deeply_nested_expression_parses() {
+    let nested = format!("print {}1{};", "(".repeat(2999), ")".repeat(2999));
+    let path = script("deep_nesting", &nested);
+    for mode in ["--backend=tree", "--backend=vm", "--check"] {
+        let output = loxide(&[mode, path.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(0));
+        assert!(output.stderr.is_empty());
+    }
+
+    let path = script(
+        "too_deep_nesting",
+        &nested.replacen("(", "((", 1).replacen(")", "))", 1),
+    );
+    let output = loxide(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{}:1: Error: Expression nesting too deep.\n",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn test_exit_codes() {
+    let clean = script("exit_clean", "1 + 2;");
+    assert_eq!(loxide(&[clean.to_str().unwrap()]).status.code(), Some(0));
+
+    let broken = script("exit_syntax", "1 +");
+    assert_eq!(loxide(&[broken.to_str().unwrap()]).status.code(), Some(65));
+
+    let bad_char = script("exit_scan", "print 1 + 2; @");
+    let output = loxide(&[bad_char.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+
+    let failing = script("exit_runtime", "-\"a\";");
+    assert_eq!(loxide(&[failing.to_str().unwrap()]).status.code(), Some(70));
+
+    assert_eq!(loxide(&["--bogus"]).status.code(), Some(64));
+}
+
+#[test]
+fn test_tokens_dump() {
+    let path = script("tokens", "1 + \"hi\" // comment\n  * 2.5");
+    let output = loxide(&["--tokens", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        concat!(
+            "   1 Number        1            1\n",
+            "   1 Plus          +\n",
+            "   1 String        \"hi\"         \"hi\"\n",
+            "   2 Star          *\n",
+            "   2 Number        2.5          2.5\n",
+            "   2 Eof\n",
+        )
+    );
+}
+
+#[test]
+fn test_tokens_dump_reports_scan_errors() {
+    let path = script("tokens_error", "1 @ 2");
+    let output = loxide(&["--tokens", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("{}:1: Error: Unexpected character: @\n", path.display())
+    );
+}
+
+#[test]
+fn test_ast_dump() {
+    let path = script(
+        "ast",
+        "print -(1 + 2.5) * \"a\" == !nil;\nvar x = 1;\n{ var y; x; }\nif (x) print x; else x = 2;\nfor (var i = 0; i < 1; i = i + 1) print i;\nfun add(a, b) { return a + b; }\nprint add(x, 2);\nclass Pair { first() { return 1; } last() {} }",
+    );
+    let output = loxide(&["--ast", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        concat!(
+            "(print (== (* (- (group (+ 1 2.5))) \"a\") (! nil)))\n",
+            "(var x 1)\n",
+            "(block (var y) (; x))\n",
+            "(if x (print x) (; (= x 2)))\n",
+            "(block (var i 0) (while (< i 1) (block (print i) (; (= i (+ i 1))))))\n",
+            "(fun add (a b) (return (+ a b)))\n",
+            "(print (call add x 2))\n",
+            "(class Pair (fun first () (return 1)) (fun last ()))\n",
+        )
+    );
+
+    let broken = script("ast_error", "(1 +");
+    let output = loxide(&["--ast", broken.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+
+    assert_eq!(
+        loxide(&["--ast", "--tokens", path.to_str().unwrap()])
+            .status
+            .code(),
+        Some(64)
+    );
+}
+
+#[test]
+fn test_ast_dump_long_sum() {
+    let path = script(
+        "ast_long",
+        &format!("print {};", vec!["1"; 100_000].join(" + ")),
+    );
+    let output = loxide(&["--ast", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("(print (+ (+ "));
+    assert!(stdout.ends_with(" 1) 1))\n"));
+}
+
+#[test]
+fn test_check_clean_file_is_silent() {
+    let path = script("check_clean", "print 1 + 2;");
+    let output = loxide(&["--check", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_check_reports_every_file() {
+    let first = script("check_first", "(1 @ 2");
+    let second = script("check_second", "-\"never run\";");
+    let third = script("check_third", "1 +\n");
+    let output = loxide(&[
+        "--check",
+        first.to_str().unwrap(),
+        second.to_str().unwrap(),
+        third.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{first}:1: Error: Unexpected character: @\n\
+             {first}:1: Error: Expect ')' after expression.\n\
+             {third}:2: Error: Expect expression.\n",
+            first = first.display(),
+            third = third.display()
+        )
+    );
+}
+
+#[test]
+fn test_check_continues_past_unreadable_file() {
+    let clean = script("check_after_missing", "print 1;");
+    let broken = script("check_after_missing_broken", "1 +");
+    let output = loxide(&[
+        "--check",
+        "/no/such/file.lox",
+        clean.to_str().unwrap(),
+        broken.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "/no/such/file.lox: Error: Could not read file: No such file or directory (os error 2).\n\
+             {broken}:1: Error: Expect expression.\n",
+            broken = broken.display()
+        )
+    );
+}
+
+#[test]
+fn test_check_reports_resolver_errors() {
+    let path = script(
+        "check_resolver",
+        "fun f() {\n  var a = 1;\n  var a = 2;\n}\nreturn;\n",
+    );
+    let output = loxide(&["--check", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{path}:3: Error: Already a variable with this name in this scope.\n\
+             {path}:5: Error: Can't return from top-level code.\n",
+            path = path.display()
+        )
+    );
+}
+
+#[test]
+fn test_prelude_shares_globals() {
+    let prelude = script(
+        "prelude_lib",
+        "fun greet(name) { return \"hi \" + name; }\nclass Box { init(v) { this.v = v; } }",
+    );
+    let main = script("prelude_main", "print greet(\"lox\");\nprint Box(3).v;");
+    let output = loxide(&[
+        "--prelude",
+        prelude.to_str().unwrap(),
+        main.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi lox\n3\n");
+    assert!(output.stderr.is_empty());
+
+    let output = loxide(&[
+        "--prelude",
+        prelude.to_str().unwrap(),
+        "-e",
+        "greet(\"repl\")",
+    ]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi repl\n");
+}
+
+#[test]
+fn test_prelude_error_stops_before_later_files() {
+    let prelude = script("prelude_broken", "print \"prelude\";\nfun f( {}");
+    let main = script("prelude_never_run", "print \"main\";\nprint 1 +;");
+    let output = loxide(&[
+        "--prelude",
+        prelude.to_str().unwrap(),
+        main.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{prelude}:2: Error: Expect parameter name.\n\
+             {main}:2: Error: Expect expression.\n",
+            prelude = prelude.display(),
+            main = main.display()
+        )
+    );
+
+    let failing = script("prelude_runtime", "print \"prelude\";\n-\"a\";");
+    let main = script("prelude_runtime_main", "print \"main\";");
+    let output = loxide(&[
+        "--prelude",
+        failing.to_str().unwrap(),
+        main.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "prelude\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{}:2: RuntimeError: Operand must be a number.\n",
+            failing.display()
+        )
+    );
+}
+
+#[test]
+fn test_eval_flag() {
+    let output = loxide(&["-e", "1 + 2"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+
+    let output = loxide(&["--eval", "1 +"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "<eval>:1: Error: Expect expression.\n"
+    );
+}
+
+#[test]
+fn test_eval_flags_join_into_one_program() {
+    let output = loxide(&["-e", "\"a\" +", "-e", "\"b\""]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ab\n");
+
+    let output = loxide(&["-e", "1 +", "-e", "-nil"]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "<eval>:2: RuntimeError: Operand must be a number.\n"
+    );
+}
+
+#[test]
+fn test_help_and_version_exit_successfully() {
+    let output = loxide(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    let help = String::from_utf8_lossy(&output.stdout);
+    assert!(help.starts_with("Usage: loxide [options] [script [args...]]\n"));
+    assert!(help.contains("--check"));
+
+    let output = loxide(&["--version"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("loxide {}\n", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn test_unknown_flag_is_usage_error() {
+    let output = loxide(&["--bogus"]);
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).starts_with("loxide: Unknown option '--bogus'.\n")
+    );
+}
+
+#[test]
+fn test_color_only_when_asked_for() {
+    let path = script("color", "-nil;");
+    let plain = loxide(&[path.to_str().unwrap()]);
+    assert!(!String::from_utf8_lossy(&plain.stderr).contains('\x1b'));
+
+    let colored = loxide(&["--color=always", path.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&colored.stderr).contains("\x1b[1;31mRuntimeError\x1b[0m"));
+
+    let never = loxide(&["--color=always", "--no-color", path.to_str().unwrap()]);
+    assert!(!String::from_utf8_lossy(&never.stderr).contains('\x1b'));
+
+    let overridden = Command::new(env!("CARGO_BIN_EXE_loxide"))
+        .args(["--color=always", path.to_str().unwrap()])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&overridden.stderr).contains("\x1b[1;31mRuntimeError"));
+}
+
+#[test]
+fn test_repl_reads_piped_input() {
+    let output = loxide_with_input(&[], "1 + 2\n\"a\" + \"b\"\r\n-nil\n4");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "> 3\n> ab\n> > 4\n> "
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "<repl>:1: RuntimeError: Operand must be a number.\n"
+    );
+
+    let output = loxide_with_input(&[], "1\nexit\n2\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "> 1\n> ");
+}